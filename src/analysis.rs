@@ -0,0 +1,383 @@
+use std::collections::HashSet;
+
+use crate::ast::{Expression, Statement};
+
+// 式の中で参照される変数名の集合。Callの名前は関数名なので含めず、引数だけを見る
+pub fn free_vars(expr: &Expression) -> HashSet<String> {
+    let mut result = HashSet::new();
+    collect_free_vars(expr, &mut result);
+    result
+}
+
+fn collect_free_vars(expr: &Expression, result: &mut HashSet<String>) {
+    match expr {
+        Expression::BinExp { lhs, rhs, .. } => {
+            collect_free_vars(lhs, result);
+            collect_free_vars(rhs, result);
+        }
+        Expression::Int { .. } | Expression::Bool { .. } => {}
+        Expression::Var { name } => {
+            result.insert(name.clone());
+        }
+        Expression::Call { args, .. } => {
+            for arg in args {
+                collect_free_vars(arg, result);
+            }
+        }
+    }
+}
+
+// 文の中でAssignによって代入される変数名の集合。
+// FuncDefの本体は別の環境で実行されるので含めない
+pub fn assigned_vars(stmt: &Statement) -> HashSet<String> {
+    let mut result = HashSet::new();
+    collect_assigned_vars(stmt, &mut result);
+    result
+}
+
+fn collect_assigned_vars(stmt: &Statement, result: &mut HashSet<String>) {
+    match stmt {
+        Statement::If { then, els, .. } => {
+            collect_assigned_vars(then, result);
+            collect_assigned_vars(els, result);
+        }
+        Statement::Switch { cases, default, .. } => {
+            for (_, stmt) in cases {
+                collect_assigned_vars(stmt, result);
+            }
+            collect_assigned_vars(default, result);
+        }
+        Statement::While { stmt, .. } | Statement::DoWhile { stmt, .. } => {
+            collect_assigned_vars(stmt, result)
+        }
+        Statement::Assign { name, .. } => {
+            result.insert(name.clone());
+        }
+        Statement::Sequence { stmts } => {
+            for stmt in stmts {
+                collect_assigned_vars(stmt, result);
+            }
+        }
+        // Letで束縛した名前への代入は外側に見えない
+        Statement::Let { name, body, .. } => {
+            let mut inner = assigned_vars(body);
+            inner.remove(name);
+            result.extend(inner);
+        }
+        Statement::Assert { .. } | Statement::FuncDef { .. } => {}
+    }
+}
+
+// 文の中で読まれる変数名の集合。条件式と代入の右辺に現れる変数をすべて集める
+pub fn used_vars(stmt: &Statement) -> HashSet<String> {
+    let mut result = HashSet::new();
+    collect_used_vars(stmt, &mut result);
+    result
+}
+
+fn collect_used_vars(stmt: &Statement, result: &mut HashSet<String>) {
+    match stmt {
+        Statement::If { cond, then, els } => {
+            collect_free_vars(cond, result);
+            collect_used_vars(then, result);
+            collect_used_vars(els, result);
+        }
+        Statement::Switch {
+            scrutinee,
+            cases,
+            default,
+        } => {
+            collect_free_vars(scrutinee, result);
+            for (_, stmt) in cases {
+                collect_used_vars(stmt, result);
+            }
+            collect_used_vars(default, result);
+        }
+        Statement::While { cond, stmt } | Statement::DoWhile { stmt, cond } => {
+            collect_free_vars(cond, result);
+            collect_used_vars(stmt, result);
+        }
+        Statement::Assign { expr, .. } | Statement::Assert { expr } => {
+            collect_free_vars(expr, result)
+        }
+        Statement::Sequence { stmts } => {
+            for stmt in stmts {
+                collect_used_vars(stmt, result);
+            }
+        }
+        Statement::Let { name, expr, body } => {
+            collect_free_vars(expr, result);
+            let mut inner = used_vars(body);
+            inner.remove(name);
+            result.extend(inner);
+        }
+        Statement::FuncDef { .. } => {}
+    }
+}
+
+// 後で読まれることのない代入を取り除く。プログラムの終了時点で生きている変数はないものとする
+pub fn eliminate_dead_stores(stmt: Statement) -> Statement {
+    eliminate_dead_stores_with(stmt, &HashSet::new())
+}
+
+// live_outは文の実行後に読まれる変数の集合。関数本体ならreturnを渡す
+pub fn eliminate_dead_stores_with(stmt: Statement, live_out: &HashSet<String>) -> Statement {
+    let (stmt, _) = eliminate(stmt, live_out.clone());
+    stmt.unwrap_or(Statement::Sequence { stmts: vec![] })
+}
+
+// 評価が必ず成功する式か。未束縛の変数、0除算やオーバーフローしうる演算、停止しないかもしれない
+// 関数呼び出しを含む式はfalseにする
+fn never_fails(expr: &Expression) -> bool {
+    match expr {
+        Expression::BinExp { op, lhs, rhs } => {
+            matches!(op.as_str(), "&" | "|" | "min" | "max" | "<" | ">")
+                && never_fails(lhs)
+                && never_fails(rhs)
+        }
+        Expression::Int { .. } | Expression::Bool { .. } => true,
+        Expression::Var { .. } | Expression::Call { .. } => false,
+    }
+}
+
+// 文の後で生きている変数の集合liveを受け取り、不要な代入を除いた文と文の前で生きている変数の集合を返す。
+// 文ごと消える場合はNoneを返す
+fn eliminate(stmt: Statement, live: HashSet<String>) -> (Option<Statement>, HashSet<String>) {
+    match stmt {
+        Statement::Assign { name, expr } => {
+            // 失敗しうる式は、消すとエラーになるはずのプログラムが成功してしまうので残す
+            if !live.contains(&name) && never_fails(&expr) {
+                return (None, live);
+            }
+            let mut live_in = live;
+            live_in.remove(&name);
+            collect_free_vars(&expr, &mut live_in);
+            (Some(Statement::Assign { name, expr }), live_in)
+        }
+        Statement::Sequence { stmts } => {
+            let mut live = live;
+            let mut kept = Vec::new();
+            for stmt in stmts.into_iter().rev() {
+                let (stmt, live_in) = eliminate(*stmt, live);
+                live = live_in;
+                if let Some(stmt) = stmt {
+                    kept.push(Box::new(stmt));
+                }
+            }
+            kept.reverse();
+            (Some(Statement::Sequence { stmts: kept }), live)
+        }
+        Statement::If { cond, then, els } => {
+            let (then, mut live_in) = eliminate(*then, live.clone());
+            let (els, els_live) = eliminate(*els, live);
+            live_in.extend(els_live);
+            collect_free_vars(&cond, &mut live_in);
+            let empty = || Statement::Sequence { stmts: vec![] };
+            let stmt = Statement::If {
+                cond,
+                then: Box::new(then.unwrap_or_else(empty)),
+                els: Box::new(els.unwrap_or_else(empty)),
+            };
+            (Some(stmt), live_in)
+        }
+        Statement::Switch {
+            scrutinee,
+            cases,
+            default,
+        } => {
+            let empty = || Box::new(Statement::Sequence { stmts: vec![] });
+            let (default, mut live_in) = eliminate(*default, live.clone());
+            let cases = cases
+                .into_iter()
+                .map(|(case, stmt)| {
+                    let (stmt, case_live) = eliminate(*stmt, live.clone());
+                    live_in.extend(case_live);
+                    (case, stmt.map_or_else(empty, Box::new))
+                })
+                .collect();
+            collect_free_vars(&scrutinee, &mut live_in);
+            let stmt = Statement::Switch {
+                scrutinee,
+                cases,
+                default: default.map_or_else(empty, Box::new),
+            };
+            (Some(stmt), live_in)
+        }
+        Statement::While { cond, stmt } => {
+            // ループ内では次の周回で読まれる変数もすべて生きているとみなす
+            let mut live_in = live;
+            collect_free_vars(&cond, &mut live_in);
+            live_in.extend(used_vars(&stmt));
+            let (body, _) = eliminate(*stmt, live_in.clone());
+            let stmt = Statement::While {
+                cond,
+                stmt: Box::new(body.unwrap_or(Statement::Sequence { stmts: vec![] })),
+            };
+            (Some(stmt), live_in)
+        }
+        Statement::DoWhile { stmt, cond } => {
+            // 本体は少なくとも一度実行されるが、Whileと同じく周回をまたいで読まれる変数を生かしておく
+            let mut live_in = live;
+            collect_free_vars(&cond, &mut live_in);
+            live_in.extend(used_vars(&stmt));
+            let (body, _) = eliminate(*stmt, live_in.clone());
+            let stmt = Statement::DoWhile {
+                stmt: Box::new(body.unwrap_or(Statement::Sequence { stmts: vec![] })),
+                cond,
+            };
+            (Some(stmt), live_in)
+        }
+        Statement::Assert { expr } => {
+            let mut live_in = live;
+            collect_free_vars(&expr, &mut live_in);
+            (Some(Statement::Assert { expr }), live_in)
+        }
+        Statement::Let { name, expr, body } => {
+            // body内のnameはLetの束縛を指し、抜けた後に外側の束縛が読まれてもbodyの代入とは無関係
+            let mut body_live = live.clone();
+            body_live.remove(&name);
+            let (body, mut live_in) = eliminate(*body, body_live);
+            live_in.remove(&name);
+            if live.contains(&name) {
+                live_in.insert(name.clone());
+            }
+            collect_free_vars(&expr, &mut live_in);
+            let stmt = Statement::Let {
+                name,
+                expr,
+                body: Box::new(body.unwrap_or(Statement::Sequence { stmts: vec![] })),
+            };
+            (Some(stmt), live_in)
+        }
+        Statement::FuncDef { .. } => (Some(stmt), live),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use anyhow::Result;
+
+    use crate::{
+        ast::{Expression, Statement},
+        parser::{parse_program, Parser},
+    };
+
+    use super::{
+        assigned_vars, eliminate_dead_stores, eliminate_dead_stores_with, free_vars, used_vars,
+    };
+
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_free_vars() -> Result<()> {
+        let expr = Parser::new("a + f(b, 2) * (c - a)").parse_expression()?;
+        assert_eq!(free_vars(&expr), set(&["a", "b", "c"]));
+        assert!(free_vars(&Parser::new("1 + 2").parse_expression()?).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_assigned_and_used_vars() -> Result<()> {
+        let stmt = parse_program(
+            "
+            {
+                i := n;
+                sum := 0;
+                while 0 < i do {
+                    if i > limit then sum := sum + i else skipped := i;
+                    i := i - 1
+                }
+            }
+            ",
+        )?;
+        assert_eq!(assigned_vars(&stmt), set(&["i", "sum", "skipped"]));
+        assert_eq!(used_vars(&stmt), set(&["n", "i", "limit", "sum"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_eliminate_dead_stores() -> Result<()> {
+        let stmt = parse_program("x := 1; x := 2; y := x; z := y + 1; w := 5")?;
+        let live_out = set(&["z"]);
+        assert_eq!(
+            eliminate_dead_stores_with(stmt.clone(), &live_out),
+            parse_program("{ x := 2; y := x; z := y + 1 }")?
+        );
+        // 変数の読み出しは未束縛なら失敗するので、zが使われなくても残す
+        assert_eq!(
+            eliminate_dead_stores(stmt),
+            parse_program("{ x := 2; y := x; z := y + 1 }")?
+        );
+        assert_eq!(
+            eliminate_dead_stores(parse_program("x := 1 < 2; y := true")?),
+            Statement::Sequence { stmts: vec![] }
+        );
+
+        // 失敗しうる代入は消さない
+        for src in ["x := f(1)", "x := 1 / 0", "x := 9223372036854775807 * 2"] {
+            let stmt = parse_program(src)?;
+            assert_eq!(eliminate_dead_stores(stmt.clone()), stmt, "{}", src);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_eliminate_dead_stores_in_loop() -> Result<()> {
+        // ループ内のiへの代入は次の周回の条件で読まれるので残す
+        let stmt = parse_program(
+            "
+            {
+                i := 10;
+                sum := 0;
+                tmp := 3;
+                while 0 < i do {
+                    sum := sum + i;
+                    i := i - 1
+                };
+                tmp := sum
+            }
+            ",
+        )?;
+        let result = eliminate_dead_stores_with(stmt.clone(), &set(&["sum"]));
+        let Statement::Sequence { stmts } = result else {
+            panic!("Expected Statement::Sequence, got {:?}", result);
+        };
+        // tmp := 3は消え、tmp := sumはsumの読み出しがあるので残る
+        assert_eq!(stmts.len(), 4);
+        assert_eq!(
+            *stmts[0],
+            Statement::Assign {
+                name: "i".to_string(),
+                expr: Box::new(Expression::Int { value: 10 }),
+            }
+        );
+        let Statement::Sequence { stmts: original } = stmt else {
+            unreachable!()
+        };
+        assert_eq!(stmts[2], original[3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_let_scope() -> Result<()> {
+        // let t = a in { t := t + 1; x := t; y := 1 }
+        let stmt = Statement::Let {
+            name: "t".to_string(),
+            expr: Box::new(Parser::new("a").parse_expression()?),
+            body: Box::new(parse_program("t := t + 1; x := t; y := 1")?),
+        };
+        assert_eq!(assigned_vars(&stmt), set(&["x", "y"]));
+        assert_eq!(used_vars(&stmt), set(&["a"]));
+
+        let result = eliminate_dead_stores_with(stmt, &set(&["x", "t"]));
+        let Statement::Let { body, .. } = result else {
+            panic!("Expected Statement::Let, got {:?}", result);
+        };
+        assert_eq!(*body, parse_program("{ t := t + 1; x := t }")?);
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
+// 整数値の型。i32では和がすぐに溢れるのでi64にしている
+pub type IntVal = i64;
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expression {
     BinExp {
         op: String,
@@ -6,7 +10,11 @@ pub enum Expression {
         rhs: Box<Expression>,
     },
     Int {
-        value: i32,
+        value: IntVal,
+    },
+    // 評価すると真は1、偽は0のIntになる
+    Bool {
+        value: bool,
     },
     Var {
         name: String,
@@ -18,16 +26,28 @@ pub enum Expression {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Statement {
     If {
         cond: Box<Expression>,
         then: Box<Statement>,
         els: Box<Statement>,
     },
+    // scrutineeの値と等しい最初のcaseだけを実行し、どれとも等しくなければdefaultを実行する
+    Switch {
+        scrutinee: Box<Expression>,
+        cases: Vec<(IntVal, Box<Statement>)>,
+        default: Box<Statement>,
+    },
     While {
         cond: Box<Expression>,
         stmt: Box<Statement>,
     },
+    // stmtを一度実行してから、condが0でない間繰り返す
+    DoWhile {
+        stmt: Box<Statement>,
+        cond: Box<Expression>,
+    },
     Assign {
         name: String,
         expr: Box<Expression>,
@@ -35,8 +55,52 @@ pub enum Statement {
     Sequence {
         stmts: Vec<Box<Statement>>,
     },
+    // exprが0なら"assertion failed"で実行を止める
+    Assert {
+        expr: Box<Expression>,
+    },
     FuncDef {
         params: Vec<String>,
         body: Box<Statement>,
     },
+    // nameをbodyの中だけで束縛する。bodyを抜けると以前の束縛に戻る
+    Let {
+        name: String,
+        expr: Box<Expression>,
+        body: Box<Statement>,
+    },
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::{Expression, Statement};
+
+    #[test]
+    fn test_serde_round_trip() {
+        let stmt = Statement::While {
+            cond: Box::new(Expression::BinExp {
+                op: "<".to_string(),
+                lhs: Box::new(Expression::Var {
+                    name: "i".to_string(),
+                }),
+                rhs: Box::new(Expression::Int { value: 10 }),
+            }),
+            stmt: Box::new(Statement::Sequence {
+                stmts: vec![Box::new(Statement::Assign {
+                    name: "i".to_string(),
+                    expr: Box::new(Expression::BinExp {
+                        op: "+".to_string(),
+                        lhs: Box::new(Expression::Var {
+                            name: "i".to_string(),
+                        }),
+                        rhs: Box::new(Expression::Int { value: 1 }),
+                    }),
+                })],
+            }),
+        };
+        let json = serde_json::to_string(&stmt).unwrap();
+        assert!(json.starts_with(r#"{"While":{"cond":{"BinExp":{"op":"<""#));
+        let back: Statement = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, stmt);
+    }
 }
@@ -0,0 +1,133 @@
+use anyhow::Result;
+
+use crate::{ast::IntVal, error::EvalError};
+
+// 評価器で共通の二項演算。比較は真なら1、偽なら0を返す。
+// オーバーフローはラップもpanicもさせずエラーにする
+pub fn apply_binop(op: &str, left: IntVal, right: IntVal) -> Result<IntVal> {
+    let result = match op {
+        "+" => left.checked_add(right),
+        "-" => left.checked_sub(right),
+        "*" => left.checked_mul(right),
+        "/" => {
+            if right == 0 {
+                anyhow::bail!(EvalError::DivByZero);
+            }
+            left.checked_div(right)
+        }
+        "^" => {
+            if right < 0 {
+                anyhow::bail!(EvalError::NegativeExponent { left, right });
+            }
+            u32::try_from(right)
+                .ok()
+                .and_then(|exp| left.checked_pow(exp))
+        }
+        "&" => Some(left & right),
+        "|" => Some(left | right),
+        // シフト量が負か型のビット幅以上ならエラー。あふれたビットは捨てる
+        "<<" | ">>" => {
+            let Some(shift) = u32::try_from(right).ok().filter(|s| *s < IntVal::BITS) else {
+                anyhow::bail!(EvalError::ShiftOutOfRange {
+                    op: op.to_string(),
+                    left,
+                    right,
+                });
+            };
+            if op == "<<" {
+                left.checked_shl(shift)
+            } else {
+                left.checked_shr(shift)
+            }
+        }
+        "min" => Some(left.min(right)),
+        "max" => Some(left.max(right)),
+        ">" => Some((left > right) as IntVal),
+        "<" => Some((left < right) as IntVal),
+        _ => anyhow::bail!(EvalError::UnknownOp(op.to_string())),
+    };
+    result.ok_or_else(|| {
+        EvalError::Overflow {
+            op: op.to_string(),
+            left,
+            right,
+        }
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{ast::IntVal, error::EvalError};
+
+    use super::apply_binop;
+
+    #[test]
+    fn test_apply_binop() -> Result<()> {
+        assert_eq!(apply_binop("+", 2, 3)?, 5);
+        assert_eq!(apply_binop("-", 2, 3)?, -1);
+        assert_eq!(apply_binop("*", 2, 3)?, 6);
+        assert_eq!(apply_binop("/", 7, 2)?, 3);
+        assert_eq!(apply_binop("^", 2, 10)?, 1024);
+        assert_eq!(apply_binop("^", -3, 0)?, 1);
+        assert_eq!(apply_binop("&", 0b1100, 0b1010)?, 0b1000);
+        assert_eq!(apply_binop("|", 0b1100, 0b1010)?, 0b1110);
+        assert_eq!(apply_binop("<<", 3, 4)?, 48);
+        assert_eq!(apply_binop(">>", -16, 2)?, -4);
+        assert_eq!(
+            apply_binop("<<", 1, 64).unwrap_err().to_string(),
+            "shift amount out of range: 1 << 64"
+        );
+        assert!(apply_binop(">>", 1, -1).is_err());
+        assert_eq!(apply_binop("min", 2, -3)?, -3);
+        assert_eq!(apply_binop("max", 2, -3)?, 2);
+        assert_eq!(apply_binop("<", 2, 3)?, 1);
+        assert_eq!(apply_binop(">", 2, 3)?, 0);
+        assert!(apply_binop("%", 2, 3).is_err());
+        assert_eq!(
+            apply_binop("/", 1, 0).unwrap_err().to_string(),
+            "division by zero"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert!(apply_binop("+", IntVal::MAX, 1).is_err());
+        assert!(apply_binop("-", IntVal::MIN, 1).is_err());
+        assert!(apply_binop("/", IntVal::MIN, -1).is_err());
+        assert!(apply_binop("^", 2, 63).is_err());
+        assert_eq!(
+            apply_binop("^", 2, -1).unwrap_err().to_string(),
+            "negative exponent: 2 ^ -1"
+        );
+        assert_eq!(
+            apply_binop("*", IntVal::MAX / 2 + 1, 2)
+                .unwrap_err()
+                .to_string(),
+            "integer overflow: 4611686018427387904 * 2"
+        );
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let kind = |op, left, right| {
+            apply_binop(op, left, right)
+                .unwrap_err()
+                .downcast::<EvalError>()
+                .unwrap()
+        };
+        assert_eq!(kind("/", 1, 0), EvalError::DivByZero);
+        assert_eq!(kind("%", 1, 0), EvalError::UnknownOp("%".to_string()));
+        assert!(matches!(
+            kind("+", IntVal::MAX, 1),
+            EvalError::Overflow {
+                left: IntVal::MAX,
+                right: 1,
+                ..
+            }
+        ));
+    }
+}
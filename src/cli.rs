@@ -0,0 +1,164 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    ast::Expression,
+    exec::{evaluate, execute, Environment},
+    func_exec_cbn, func_exec_cbv,
+    parser::{parse_expression, parse_program, parse_program_with_functions},
+    tree_machine,
+};
+
+// ファイルを実行する評価器。既定は値呼びのfunc_exec_cbv
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Cbv,
+    Cbn,
+    Tree,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cbv" => Ok(Backend::Cbv),
+            "cbn" => Ok(Backend::Cbn),
+            "tree" => Ok(Backend::Tree),
+            _ => anyhow::bail!("Unknown backend: {} (expected cbv, cbn or tree)", s),
+        }
+    }
+}
+
+// 選んだ評価器でプログラムを実行し、変数の値を並べた環境を返す
+pub fn run_source(src: &str, backend: Backend) -> Result<Environment> {
+    match backend {
+        Backend::Cbv => {
+            let (stmt, func_env) = parse_program_with_functions(src)?;
+            func_exec_cbv::execute(&stmt, &HashMap::new(), &func_env)
+        }
+        Backend::Cbn => {
+            let (stmt, func_env) = parse_program_with_functions(src)?;
+            let env = func_exec_cbn::execute(Box::new(stmt), Box::default(), Box::new(func_env))?;
+            env.iter()
+                .map(|(name, thunk)| Ok((name.clone(), thunk.force()?)))
+                .collect()
+        }
+        Backend::Tree => tree_machine::execute(&parse_program(src)?, &HashMap::new()),
+    }
+}
+
+// ファイルを読み込んで実行し、最終的な環境を返す
+pub fn run_file(path: impl AsRef<Path>, backend: Backend) -> Result<Environment> {
+    let path = path.as_ref();
+    let src =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    run_source(&src, backend).with_context(|| format!("failed to run {}", path.display()))
+}
+
+// 環境を変数名の順に「name = value」の形で1行ずつ並べる
+pub fn format_environment(env: &Environment) -> String {
+    let mut names: Vec<&String> = env.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| match &env[name] {
+            Expression::Int { value } => format!("{} = {}\n", name, value),
+            expr => format!("{} = {:?}\n", name, expr),
+        })
+        .collect()
+}
+
+// 1行を式として評価できればその値を、文として実行できれば何も返さない。
+// 失敗したときはenvを変更しない
+pub fn eval_line(line: &str, env: &mut Environment) -> Result<Option<Expression>> {
+    if let Ok(expr) = parse_expression(line) {
+        return evaluate(expr, env.clone()).map(Some);
+    }
+    *env = execute(parse_program(line)?, env.clone())?;
+    Ok(None)
+}
+
+// 1行ずつ読んで実行し、式の値やエラーをoutputに書く。環境は行をまたいで引き継ぐ
+pub fn repl(input: impl BufRead, output: &mut impl Write, env: &mut Environment) -> Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match eval_line(&line, env) {
+            Ok(Some(Expression::Int { value })) => writeln!(output, "{}", value)?,
+            Ok(Some(expr)) => writeln!(output, "{:?}", expr)?,
+            Ok(None) => {}
+            Err(err) => writeln!(output, "error: {}", err)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+
+    use crate::ast::Expression;
+
+    use super::{repl, run_source, Backend};
+
+    #[test]
+    fn test_repl() -> Result<()> {
+        let input = "
+            i := 3
+            sum := 0
+            i + 1
+            y
+            while 0 < i do { sum := sum + i; i := i - 1 }
+            i := (
+            sum * 2
+        ";
+        let mut output = Vec::new();
+        let mut env = HashMap::new();
+        repl(input.as_bytes(), &mut output, &mut env)?;
+
+        let output = String::from_utf8(output)?;
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "4");
+        assert!(lines[1].starts_with("error: Unknown variable: y"));
+        assert!(lines[2].starts_with("error:"));
+        assert_eq!(lines[3], "12");
+        assert_eq!(env["i"], Expression::Int { value: 0 });
+        assert_eq!(env["sum"], Expression::Int { value: 6 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_backends_agree() -> Result<()> {
+        let src = "i := 7; sum := 0; while 0 < i do { if i > 3 then sum := sum + i * 2 else sum := sum + i; i := i - 1 }";
+        let expected = run_source(src, Backend::Cbv)?;
+        assert_eq!(expected["sum"], Expression::Int { value: 50 });
+        assert_eq!(run_source(src, Backend::Cbn)?, expected);
+        assert_eq!(run_source(src, Backend::Tree)?, expected);
+
+        let src =
+            "def sum(n) { if n < 1 then return := 0 else return := n + sum(n - 1) }; x := sum(4)";
+        assert_eq!(
+            run_source(src, Backend::Cbv)?,
+            run_source(src, Backend::Cbn)?
+        );
+        assert!(run_source(src, Backend::Tree).is_err());
+
+        assert_eq!("cbn".parse::<Backend>()?, Backend::Cbn);
+        assert!("lazy".parse::<Backend>().is_err());
+        Ok(())
+    }
+}
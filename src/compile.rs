@@ -0,0 +1,73 @@
+use anyhow::Result;
+
+use crate::{
+    ast::Expression,
+    stack_machine::{StackInstruction, StackOperation},
+};
+
+// 式を後置記法の命令列に変換する。スタックマシンには変数も比較演算もないので、
+// Var・Call・比較はエラーにする
+pub fn compile_expr(expr: &Expression) -> Result<Vec<StackInstruction>> {
+    let mut instructions = Vec::new();
+    emit_expr(expr, &mut instructions)?;
+    Ok(instructions)
+}
+
+fn emit_expr(expr: &Expression, instructions: &mut Vec<StackInstruction>) -> Result<()> {
+    match expr {
+        Expression::BinExp { op, lhs, rhs } => {
+            let operation = match op.as_str() {
+                "+" => StackOperation::Add,
+                "-" => StackOperation::Sub,
+                "*" => StackOperation::Mul,
+                "/" => StackOperation::Div,
+                "^" => StackOperation::Pow,
+                "min" => StackOperation::Min,
+                "max" => StackOperation::Max,
+                _ => anyhow::bail!("op {} is not supported by the stack machine", op),
+            };
+            emit_expr(lhs, instructions)?;
+            emit_expr(rhs, instructions)?;
+            instructions.push(StackInstruction::Operation(operation));
+        }
+        Expression::Int { value } => {
+            instructions.push(StackInstruction::Operation(StackOperation::Push));
+            instructions.push(StackInstruction::Data(*value));
+        }
+        Expression::Bool { value } => {
+            instructions.push(StackInstruction::Operation(StackOperation::Push));
+            instructions.push(StackInstruction::Data((*value).into()));
+        }
+        _ => anyhow::bail!("{:?} is not supported by the stack machine", expr),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{ast::Expression, eval_const::eval_const, parser::Parser, stack_machine::execute};
+
+    use super::compile_expr;
+
+    #[test]
+    fn test_compile_expr() -> Result<()> {
+        for src in ["2 + 3 * 4", "(2 + 3) * 4", "10 - 4 - 3", "8 / 2 * 3", "7"] {
+            let expr = Parser::new(src).parse_expression()?;
+            let expected = match eval_const(expr.clone())? {
+                Expression::Int { value } => value,
+                other => panic!("Expected Expression::Int, got {:?}", other),
+            };
+            assert_eq!(execute(compile_expr(&expr)?, vec![])?, expected, "{}", src);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile_expr_unsupported() -> Result<()> {
+        assert!(compile_expr(&Parser::new("x + 1").parse_expression()?).is_err());
+        assert!(compile_expr(&Parser::new("1 < 2").parse_expression()?).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use thiserror::Error;
+
+use crate::ast::{Expression, IntVal};
+
+// 評価器が返すエラー。呼び出し側はanyhow::Errorからdowncast_refで取り出して種類を調べられる
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum EvalError {
+    #[error("Unknown variable: {0}")]
+    UnknownVariable(String),
+    #[error("Unknown function: {0}")]
+    UnknownFunction(String),
+    #[error("Unknown op: {0}")]
+    UnknownOp(String),
+    #[error("Expected to Expression::Int {0:?}")]
+    TypeMismatch(Expression),
+    #[error("division by zero")]
+    DivByZero,
+    #[error("integer overflow: {left} {op} {right}")]
+    Overflow {
+        op: String,
+        left: IntVal,
+        right: IntVal,
+    },
+    #[error("negative exponent: {left} ^ {right}")]
+    NegativeExponent { left: IntVal, right: IntVal },
+    #[error("shift amount out of range: {left} {op} {right}")]
+    ShiftOutOfRange {
+        op: String,
+        left: IntVal,
+        right: IntVal,
+    },
+    #[error("{name} expects {expected} arguments but got {found}")]
+    Arity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Expected to return value")]
+    MissingReturn,
+    #[error("loop step limit exceeded")]
+    LoopLimitExceeded,
+    #[error("out of gas")]
+    OutOfGas,
+    #[error("assertion failed: {0:?}")]
+    AssertionFailed(Expression),
+    #[error("stack is empty")]
+    StackUnderflow,
+    #[error("jump target {0} is out of range")]
+    JumpOutOfRange(usize),
+    #[error("return with an empty call stack")]
+    EmptyCallStack,
+}
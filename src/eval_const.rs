@@ -1,6 +1,9 @@
 use anyhow::Result;
 
-use crate::ast::Expression;
+use crate::{
+    ast::{Expression, Statement},
+    binop::apply_binop,
+};
 
 pub fn eval_const(expr: Expression) -> Result<Expression> {
     match expr {
@@ -12,39 +15,152 @@ pub fn eval_const(expr: Expression) -> Result<Expression> {
             } else {
                 anyhow::bail!("Expected to Expression::Int but {:?}", left);
             };
-            let right_value = if let Expression::Int { value } = left {
+            let right_value = if let Expression::Int { value } = right {
                 value
             } else {
                 anyhow::bail!("Expected to Expression::Int but {:?}", right);
             };
-            match op.as_str() {
-                "+" => Ok(Expression::Int {
-                    value: left_value + right_value,
-                }),
-                "-" => Ok(Expression::Int {
-                    value: left_value - right_value,
-                }),
-                "*" => Ok(Expression::Int {
-                    value: left_value * right_value,
-                }),
-                "/" => Ok(Expression::Int {
-                    value: left_value / right_value,
-                }),
-                _ => anyhow::bail!("Unknown op: {}", op),
-            }
+            Ok(Expression::Int {
+                value: apply_binop(&op, left_value, right_value)?,
+            })
         }
         Expression::Int { value } => Ok(Expression::Int { value }),
+        Expression::Bool { value } => Ok(Expression::Int {
+            value: value.into(),
+        }),
         _ => anyhow::bail!("Unknown expression: {:?}", expr),
     }
 }
 
+// 定数の部分式だけを畳み込む。変数や関数呼び出しを含む式はその部分を残す。
+// 1 / 0のように評価に失敗する部分式は、実行時に同じエラーになるよう畳み込まずに残す
+fn fold_expression(expr: Expression) -> Result<Expression> {
+    match expr {
+        Expression::BinExp { op, lhs, rhs } => {
+            let lhs = fold_expression(*lhs)?;
+            let rhs = fold_expression(*rhs)?;
+            let folded = Expression::BinExp {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+            match &folded {
+                Expression::BinExp { lhs, rhs, .. }
+                    if matches!(**lhs, Expression::Int { .. })
+                        && matches!(**rhs, Expression::Int { .. }) =>
+                {
+                    Ok(eval_const(folded.clone()).unwrap_or(folded))
+                }
+                _ => Ok(folded),
+            }
+        }
+        Expression::Call { name, args } => Ok(Expression::Call {
+            name,
+            args: args
+                .into_iter()
+                .map(|arg| fold_expression(*arg).map(Box::new))
+                .collect::<Result<_>>()?,
+        }),
+        Expression::Bool { .. } => eval_const(expr),
+        Expression::Int { .. } | Expression::Var { .. } => Ok(expr),
+    }
+}
+
+// 条件式と代入の右辺を畳み込み、条件が定数になったIfは実行される側の枝だけを残す
+pub fn fold_statement(stmt: Statement) -> Result<Statement> {
+    match stmt {
+        Statement::If { cond, then, els } => match fold_expression(*cond)? {
+            Expression::Int { value } if value != 0 => fold_statement(*then),
+            Expression::Int { .. } => fold_statement(*els),
+            cond => Ok(Statement::If {
+                cond: Box::new(cond),
+                then: Box::new(fold_statement(*then)?),
+                els: Box::new(fold_statement(*els)?),
+            }),
+        },
+        Statement::Switch {
+            scrutinee,
+            cases,
+            default,
+        } => {
+            let scrutinee = fold_expression(*scrutinee)?;
+            if let Expression::Int { value } = scrutinee {
+                let stmt = cases
+                    .into_iter()
+                    .find(|(case, _)| *case == value)
+                    .map_or(default, |(_, stmt)| stmt);
+                return fold_statement(*stmt);
+            }
+            Ok(Statement::Switch {
+                scrutinee: Box::new(scrutinee),
+                cases: cases
+                    .into_iter()
+                    .map(|(case, stmt)| Ok((case, Box::new(fold_statement(*stmt)?))))
+                    .collect::<Result<_>>()?,
+                default: Box::new(fold_statement(*default)?),
+            })
+        }
+        Statement::While { cond, stmt } => {
+            let cond = fold_expression(*cond)?;
+            if let Expression::Int { value: 0 } = cond {
+                return Ok(Statement::Sequence { stmts: vec![] });
+            }
+            Ok(Statement::While {
+                cond: Box::new(cond),
+                stmt: Box::new(fold_statement(*stmt)?),
+            })
+        }
+        Statement::DoWhile { stmt, cond } => {
+            // 条件が0なら本体を一度だけ実行するのと同じ
+            let cond = fold_expression(*cond)?;
+            let stmt = fold_statement(*stmt)?;
+            if let Expression::Int { value: 0 } = cond {
+                return Ok(stmt);
+            }
+            Ok(Statement::DoWhile {
+                stmt: Box::new(stmt),
+                cond: Box::new(cond),
+            })
+        }
+        Statement::Assign { name, expr } => Ok(Statement::Assign {
+            name,
+            expr: Box::new(fold_expression(*expr)?),
+        }),
+        Statement::Sequence { stmts } => Ok(Statement::Sequence {
+            stmts: stmts
+                .into_iter()
+                .map(|stmt| fold_statement(*stmt).map(Box::new))
+                .collect::<Result<_>>()?,
+        }),
+        // 常に成り立つAssertは取り除く。常に失敗するものは実行時にエラーにするため残す
+        Statement::Assert { expr } => match fold_expression(*expr)? {
+            Expression::Int { value } if value != 0 => Ok(Statement::Sequence { stmts: vec![] }),
+            expr => Ok(Statement::Assert {
+                expr: Box::new(expr),
+            }),
+        },
+        Statement::Let { name, expr, body } => Ok(Statement::Let {
+            name,
+            expr: Box::new(fold_expression(*expr)?),
+            body: Box::new(fold_statement(*body)?),
+        }),
+        Statement::FuncDef { params, body } => Ok(Statement::FuncDef {
+            params,
+            body: Box::new(fold_statement(*body)?),
+        }),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
 
-    use crate::ast::Expression;
+    use crate::{
+        ast::{Expression, Statement},
+        parser::{parse_expression, parse_program},
+    };
 
-    use super::eval_const;
+    use super::{eval_const, fold_statement};
 
     #[test]
     fn four_arithmetic_ops1() -> Result<()> {
@@ -65,8 +181,52 @@ mod tests {
         if let Expression::Int { value } = res {
             assert_eq!(value, 1);
         } else {
-            assert!(false, "Expected Expression::Int, got {:?}", res);
+            panic!("Expected Expression::Int, got {:?}", res);
         }
         Ok(())
     }
+
+    #[test]
+    fn test_fold_statement() -> Result<()> {
+        let stmt = parse_program("if 1 < 2 then x := 1 else x := 2")?;
+        assert_eq!(fold_statement(stmt)?, parse_program("x := 1")?);
+
+        let stmt = parse_program("while x < 2 * 3 do { y := x + (4 - 1); x := x + 1 }")?;
+        assert_eq!(
+            fold_statement(stmt)?,
+            parse_program("while x < 6 do { y := x + 3; x := x + 1 }")?
+        );
+
+        let stmt = parse_program("{ while 2 < 1 do x := 1; z := f(1 + 1) }")?;
+        assert_eq!(
+            fold_statement(stmt)?,
+            Statement::Sequence {
+                stmts: vec![
+                    Box::new(Statement::Sequence { stmts: vec![] }),
+                    Box::new(parse_program("z := f(2)")?),
+                ],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_division_by_zero() -> Result<()> {
+        let err = eval_const(parse_expression("1 / (2 - 2)")?).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero");
+        // 失敗する式は畳み込まずに残し、実行時にエラーにする
+        let stmt = parse_program("x := 1 / 0")?;
+        assert_eq!(fold_statement(stmt.clone())?, stmt);
+        assert_eq!(
+            fold_statement(parse_program("x := (2 - 2) + 1 / (3 - 3)")?)?,
+            parse_program("x := 0 + 1 / 0")?
+        );
+
+        // 捨てられる枝の中の失敗する式はエラーにならない
+        assert_eq!(
+            fold_statement(parse_program("if 1 then x := 1 else x := 1 / 0")?)?,
+            parse_program("x := 1")?
+        );
+        Ok(())
+    }
 }
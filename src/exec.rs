@@ -2,74 +2,167 @@ use std::collections::HashMap;
 
 use anyhow::Result;
 
-use crate::ast::{Expression, Statement};
+use crate::{
+    ast::{Expression, IntVal, Statement},
+    binop::apply_binop,
+    error::EvalError,
+    parser::parse_program,
+};
 
-type Environment = HashMap<String, Expression>;
+pub type Environment = HashMap<String, Expression>;
 
 pub fn evaluate(expr: Expression, env: Environment) -> Result<Expression> {
+    eval_expr(expr, env, &mut Budget::default())
+}
+
+// 実行の上限。loop_limitは1つのループの周回数、gasはプログラム全体で評価できる文と式の数
+#[derive(Default)]
+struct Budget {
+    loop_limit: Option<usize>,
+    gas: Option<u64>,
+}
+
+impl Budget {
+    // 文か式を1つ評価するたびに呼ぶ
+    fn consume(&mut self) -> Result<()> {
+        if let Some(gas) = &mut self.gas {
+            if *gas == 0 {
+                anyhow::bail!(EvalError::OutOfGas);
+            }
+            *gas -= 1;
+        }
+        Ok(())
+    }
+}
+
+fn eval_expr(expr: Expression, env: Environment, budget: &mut Budget) -> Result<Expression> {
+    budget.consume()?;
     match expr {
-        Expression::Var { name } => Ok(env[&name].clone()),
+        Expression::Var { name } => env
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| EvalError::UnknownVariable(name.clone()).into()),
         Expression::BinExp { op, lhs, rhs } => {
-            let left = evaluate(*lhs, env.clone())?;
-            let right = evaluate(*rhs, env.clone())?;
+            let left = eval_expr(*lhs, env.clone(), budget)?;
+            let right = eval_expr(*rhs, env.clone(), budget)?;
             let left_value = if let Expression::Int { value } = left {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", left);
+                anyhow::bail!(EvalError::TypeMismatch(left));
             };
             let right_value = if let Expression::Int { value } = right {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", right);
+                anyhow::bail!(EvalError::TypeMismatch(right));
             };
-            match op.as_str() {
-                "+" => Ok(Expression::Int {
-                    value: left_value + right_value,
-                }),
-                "-" => Ok(Expression::Int {
-                    value: left_value - right_value,
-                }),
-                "*" => Ok(Expression::Int {
-                    value: left_value * right_value,
-                }),
-                "/" => Ok(Expression::Int {
-                    value: left_value / right_value,
-                }),
-                _ => anyhow::bail!("Unknown op: {}", op),
-            }
+            Ok(Expression::Int {
+                value: apply_binop(&op, left_value, right_value)?,
+            })
         }
         Expression::Int { value } => Ok(Expression::Int { value }),
+        Expression::Bool { value } => Ok(Expression::Int {
+            value: value.into(),
+        }),
         _ => anyhow::bail!("Unknown expression: {:?}", expr),
     }
 }
 
+// 空の環境で式を評価し、整数値を取り出す
+pub fn eval_arith(expr: Expression) -> Result<IntVal> {
+    match evaluate(expr, HashMap::new())? {
+        Expression::Int { value } => Ok(value),
+        other => anyhow::bail!(EvalError::TypeMismatch(other)),
+    }
+}
+
 pub fn execute(stmt: Statement, env: Environment) -> Result<Environment> {
+    execute_with_limit(stmt, env, None)
+}
+
+// limitを超えて回ったループがあればエラーにする
+pub fn execute_with_limit(
+    stmt: Statement,
+    env: Environment,
+    limit: Option<usize>,
+) -> Result<Environment> {
+    let mut budget = Budget {
+        loop_limit: limit,
+        ..Default::default()
+    };
+    run(stmt, env, &mut budget)
+}
+
+// 文と式を合わせてgas回より多く評価しようとしたらエラーにする
+pub fn execute_with_gas(stmt: Statement, env: Environment, gas: u64) -> Result<Environment> {
+    let mut budget = Budget {
+        gas: Some(gas),
+        ..Default::default()
+    };
+    run(stmt, env, &mut budget)
+}
+
+fn run(stmt: Statement, env: Environment, budget: &mut Budget) -> Result<Environment> {
+    budget.consume()?;
     match stmt {
         Statement::If { cond, then, els } => {
-            let cond = evaluate(*cond, env.clone())?;
+            let cond = eval_expr(*cond, env.clone(), budget)?;
             let cond_value = if let Expression::Int { value } = cond {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", cond);
+                anyhow::bail!(EvalError::TypeMismatch(cond));
             };
             if cond_value != 0 {
-                execute(*then, env)
+                run(*then, env, budget)
             } else {
-                execute(*els, env)
+                run(*els, env, budget)
             }
         }
+        Statement::Switch {
+            scrutinee,
+            cases,
+            default,
+        } => {
+            let value = eval_expr(*scrutinee, env.clone(), budget)?;
+            let Expression::Int { value } = value else {
+                anyhow::bail!(EvalError::TypeMismatch(value));
+            };
+            let stmt = cases
+                .into_iter()
+                .find(|(case, _)| *case == value)
+                .map_or(default, |(_, stmt)| stmt);
+            run(*stmt, env, budget)
+        }
         Statement::While { cond, stmt } => {
             let mut current_env = env.clone();
-            while let Expression::Int { value } = evaluate(*cond.clone(), current_env.clone())? {
+            let mut iterations = 0;
+            while let Expression::Int { value } =
+                eval_expr(*cond.clone(), current_env.clone(), budget)?
+            {
                 if value == 0 {
                     break;
                 }
-                current_env = execute((*stmt).clone(), current_env.clone())?;
+                count_iteration(&mut iterations, budget.loop_limit)?;
+                current_env = run((*stmt).clone(), current_env.clone(), budget)?;
+            }
+            Ok(current_env)
+        }
+        Statement::DoWhile { stmt, cond } => {
+            let mut iterations = 0;
+            count_iteration(&mut iterations, budget.loop_limit)?;
+            let mut current_env = run((*stmt).clone(), env, budget)?;
+            while let Expression::Int { value } =
+                eval_expr(*cond.clone(), current_env.clone(), budget)?
+            {
+                if value == 0 {
+                    break;
+                }
+                count_iteration(&mut iterations, budget.loop_limit)?;
+                current_env = run((*stmt).clone(), current_env.clone(), budget)?;
             }
             Ok(current_env)
         }
         Statement::Assign { name, expr } => {
-            let value = evaluate(*expr, env.clone())?;
+            let value = eval_expr(*expr, env.clone(), budget)?;
             let mut current_env = env.clone();
             current_env.insert(name, value);
             Ok(current_env)
@@ -77,22 +170,133 @@ pub fn execute(stmt: Statement, env: Environment) -> Result<Environment> {
         Statement::Sequence { stmts } => {
             let mut current_env = env.clone();
             for stmt in stmts {
-                current_env = execute(*stmt, current_env)?;
+                current_env = run(*stmt, current_env, budget)?;
+            }
+            Ok(current_env)
+        }
+        Statement::Assert { expr } => {
+            let value = eval_expr(*expr.clone(), env.clone(), budget)?;
+            if value == (Expression::Int { value: 0 }) {
+                anyhow::bail!(EvalError::AssertionFailed(*expr));
             }
+            Ok(env)
+        }
+        Statement::Let { name, expr, body } => {
+            let value = eval_expr(*expr, env.clone(), budget)?;
+            let previous = env.get(&name).cloned();
+            let mut current_env = env;
+            current_env.insert(name.clone(), value);
+            let mut current_env = run(*body, current_env, budget)?;
+            restore_binding(&mut current_env, name, previous);
             Ok(current_env)
         }
         _ => anyhow::bail!("Unknown statement: {:?}", stmt),
     }
 }
 
+// ループを1周するたびに呼ぶ。周回数がlimitを超えたらエラーにする
+pub fn count_iteration(iterations: &mut usize, limit: Option<usize>) -> Result<()> {
+    *iterations += 1;
+    if limit.is_some_and(|limit| *iterations > limit) {
+        anyhow::bail!(EvalError::LoopLimitExceeded);
+    }
+    Ok(())
+}
+
+// Letを抜けるときにnameの束縛を元に戻す。もともと束縛がなければ取り除く
+pub fn restore_binding(env: &mut Environment, name: String, previous: Option<Expression>) {
+    match previous {
+        Some(value) => env.insert(name, value),
+        None => env.remove(&name),
+    };
+}
+
+// Sequenceを1文ずつ実行する。nextのたびに1文だけ進め、その後の環境を返す。
+// 入れ子のSequenceやループの中は1文として一度に実行する。エラーが起きたらそれ以降は進まない
+pub struct Stepper {
+    remaining: Vec<Statement>,
+    env: Environment,
+}
+
+impl Stepper {
+    pub fn new(stmt: Statement, env: Environment) -> Stepper {
+        let mut remaining = match stmt {
+            Statement::Sequence { stmts } => stmts.into_iter().map(|stmt| *stmt).collect(),
+            stmt => vec![stmt],
+        };
+        remaining.reverse();
+        Stepper { remaining, env }
+    }
+
+    pub fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    // 次に実行される文
+    pub fn peek(&self) -> Option<&Statement> {
+        self.remaining.last()
+    }
+}
+
+impl Iterator for Stepper {
+    type Item = Result<Environment>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let stmt = self.remaining.pop()?;
+        match execute(stmt, self.env.clone()) {
+            Ok(env) => {
+                self.env = env;
+                Some(Ok(self.env.clone()))
+            }
+            Err(err) => {
+                self.remaining.clear();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+// afterで追加・変更された変数を(名前, 変更前の値, 変更後の値)として名前順に返す。
+// 取り除かれた変数と整数でない値は含めない
+pub fn env_diff(
+    before: &Environment,
+    after: &Environment,
+) -> Vec<(String, Option<IntVal>, IntVal)> {
+    let int = |expr: Option<&Expression>| match expr {
+        Some(Expression::Int { value }) => Some(*value),
+        _ => None,
+    };
+    let mut diff: Vec<_> = after
+        .iter()
+        .filter_map(|(name, value)| {
+            let new = int(Some(value))?;
+            let old = int(before.get(name));
+            (old != Some(new)).then(|| (name.clone(), old, new))
+        })
+        .collect();
+    diff.sort();
+    diff
+}
+
+pub fn eval_source(src: &str) -> Result<Environment> {
+    execute(parse_program(src)?, HashMap::new())
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
     use std::collections::HashMap;
 
-    use crate::ast::{Expression, Statement};
+    use crate::{
+        ast::{Expression, Statement},
+        error::EvalError,
+        parser::{parse_expression, parse_program},
+        testutil::assert_env_eq,
+    };
 
-    use super::execute;
+    use super::{
+        env_diff, eval_arith, eval_source, execute, execute_with_gas, execute_with_limit, Stepper,
+    };
 
     #[test]
     fn test_statement() -> Result<()> {
@@ -126,10 +330,6 @@ mod tests {
 
     #[test]
     fn test_statement2() -> Result<()> {
-        let mut expect_env = HashMap::new();
-        expect_env.insert(String::from("i"), Expression::Int { value: 0 });
-        expect_env.insert(String::from("sum"), Expression::Int { value: 55 });
-
         let stmt = Statement::Sequence {
             stmts: vec![
                 Box::new(Statement::Assign {
@@ -175,8 +375,319 @@ mod tests {
         };
         let res_env = execute(stmt, HashMap::new())?;
 
-        assert_eq!(expect_env, res_env);
+        assert_env_eq(&res_env, &[("i", 0), ("sum", 55)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_source() -> Result<()> {
+        let sample = "
+        {
+            i := 10;
+            sum := 0;
+            while i do {
+                sum := sum + i;
+                i := i - 1
+            }
+        }
+        ";
+        let res_env = eval_source(sample)?;
+        assert_env_eq(&res_env, &[("sum", 55), ("i", 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let err = eval_source("x := 0; y := 1 / x").unwrap_err();
+        assert_eq!(err.to_string(), "division by zero");
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let err = eval_source("x := 1; y := x + z").unwrap_err();
+        assert_eq!(err.to_string(), "Unknown variable: z");
+    }
+
+    #[test]
+    fn test_overflow() {
+        let err = eval_source("x := 9223372036854775807; y := x * 2").unwrap_err();
+        assert!(err.to_string().starts_with("integer overflow"));
+        assert!(eval_source("sum := 1; while 0 < sum do sum := sum * 3").is_err());
+    }
+
+    #[test]
+    fn test_let() -> Result<()> {
+        // let t = 5 in { t := t * 2; x := t }
+        let stmt = Statement::Let {
+            name: String::from("t"),
+            expr: Box::new(Expression::Int { value: 5 }),
+            body: Box::new(Statement::Sequence {
+                stmts: vec![
+                    Box::new(Statement::Assign {
+                        name: String::from("t"),
+                        expr: Box::new(Expression::BinExp {
+                            op: String::from("*"),
+                            lhs: Box::new(Expression::Var {
+                                name: String::from("t"),
+                            }),
+                            rhs: Box::new(Expression::Int { value: 2 }),
+                        }),
+                    }),
+                    Box::new(Statement::Assign {
+                        name: String::from("x"),
+                        expr: Box::new(Expression::Var {
+                            name: String::from("t"),
+                        }),
+                    }),
+                ],
+            }),
+        };
+        let env = execute(stmt.clone(), HashMap::new())?;
+        assert_eq!(env.get("t"), None);
+        assert_eq!(env["x"], Expression::Int { value: 10 });
+
+        let mut outer = HashMap::new();
+        outer.insert(String::from("t"), Expression::Int { value: 1 });
+        let env = execute(stmt, outer)?;
+        assert_eq!(env["t"], Expression::Int { value: 1 });
+        assert_eq!(env["x"], Expression::Int { value: 10 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_exceeds_i32() -> Result<()> {
+        let env = eval_source("x := 2147483647; y := x * 4; z := 5000000000 - y")?;
+        assert_eq!(env["y"], Expression::Int { value: 8589934588 });
+        assert!(matches!(env["y"], Expression::Int { value } if value > i32::MAX.into()));
+        assert_eq!(env["z"], Expression::Int { value: -3589934588 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_do_while() -> Result<()> {
+        // 条件は最初から偽だが、本体は一度だけ実行される
+        let stmt = Statement::DoWhile {
+            stmt: Box::new(parse_program("count := count + 1")?),
+            cond: Box::new(parse_expression("count < 0")?),
+        };
+        let mut env = HashMap::new();
+        env.insert(String::from("count"), Expression::Int { value: 0 });
+        let env = execute(stmt, env)?;
+        assert_eq!(env["count"], Expression::Int { value: 1 });
+
+        let stmt = Statement::DoWhile {
+            stmt: Box::new(parse_program("i := i + 1")?),
+            cond: Box::new(parse_expression("i < 5")?),
+        };
+        let env = execute(
+            stmt,
+            HashMap::from([(String::from("i"), Expression::Int { value: 0 })]),
+        )?;
+        assert_eq!(env["i"], Expression::Int { value: 5 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_assert() -> Result<()> {
+        let passing = Statement::Assert {
+            expr: Box::new(parse_expression("2 < x")?),
+        };
+        let env = HashMap::from([(String::from("x"), Expression::Int { value: 3 })]);
+        assert_eq!(execute(passing, env.clone())?, env);
+
+        // 失敗したAssertの後の代入は実行されない
+        let failing = Statement::Sequence {
+            stmts: vec![
+                Box::new(Statement::Assert {
+                    expr: Box::new(parse_expression("x < 3")?),
+                }),
+                Box::new(parse_program("y := 1")?),
+            ],
+        };
+        let err = execute(failing, env).unwrap_err();
+        assert!(err.to_string().starts_with("assertion failed"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_arith() -> Result<()> {
+        assert_eq!(eval_arith(parse_expression("(2 + 3) * 4")?)?, 20);
+        assert!(eval_arith(parse_expression("x + 1")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pow() -> Result<()> {
+        let pow = |lhs, rhs| Expression::BinExp {
+            op: String::from("^"),
+            lhs: Box::new(Expression::Int { value: lhs }),
+            rhs: Box::new(Expression::Int { value: rhs }),
+        };
+        assert_eq!(eval_arith(pow(2, 10))?, 1024);
+        assert!(eval_arith(pow(2, -1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_and_max() -> Result<()> {
+        let env = HashMap::from([
+            (String::from("a"), Expression::Int { value: -4 }),
+            (String::from("b"), Expression::Int { value: 9 }),
+        ]);
+        let binexp = |op: &str| Statement::Assign {
+            name: String::from(op),
+            expr: Box::new(Expression::BinExp {
+                op: String::from(op),
+                lhs: Box::new(Expression::Var {
+                    name: String::from("a"),
+                }),
+                rhs: Box::new(Expression::Var {
+                    name: String::from("b"),
+                }),
+            }),
+        };
+        let stmt = Statement::Sequence {
+            stmts: vec![Box::new(binexp("min")), Box::new(binexp("max"))],
+        };
+        let env = execute(stmt, env)?;
+        assert_env_eq(&env, &[("min", -4), ("max", 9)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitwise() -> Result<()> {
+        let binexp = |op: &str, lhs, rhs| Expression::BinExp {
+            op: String::from(op),
+            lhs: Box::new(Expression::Int { value: lhs }),
+            rhs: Box::new(Expression::Int { value: rhs }),
+        };
+        assert_eq!(eval_arith(binexp("&", 6, 3))?, 2);
+        assert_eq!(eval_arith(binexp("|", 6, 3))?, 7);
+        assert_eq!(eval_arith(binexp("<<", 6, 3))?, 48);
+        assert_eq!(eval_arith(binexp(">>", 6, 1))?, 3);
+        assert!(eval_arith(binexp("<<", 6, 64)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch() -> Result<()> {
+        let stmt = Statement::Switch {
+            scrutinee: Box::new(parse_expression("x")?),
+            cases: vec![
+                (1, Box::new(parse_program("y := 10")?)),
+                (2, Box::new(parse_program("y := 20")?)),
+                (3, Box::new(parse_program("y := 30")?)),
+            ],
+            default: Box::new(parse_program("y := 0")?),
+        };
+        for (x, y) in [(1, 10), (2, 20), (3, 30), (4, 0)] {
+            let env = HashMap::from([(String::from("x"), Expression::Int { value: x })]);
+            let env = execute(stmt.clone(), env)?;
+            assert_env_eq(&env, &[("x", x), ("y", y)]);
+        }
+
+        // 選ばれなかった枝は実行されない
+        let stmt = Statement::Switch {
+            scrutinee: Box::new(parse_expression("1 + 1")?),
+            cases: vec![
+                (1, Box::new(parse_program("y := 1 / 0")?)),
+                (2, Box::new(parse_program("y := 2")?)),
+            ],
+            default: Box::new(parse_program("y := 1 / 0")?),
+        };
+        assert_env_eq(&execute(stmt, HashMap::new())?, &[("y", 2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_diff() -> Result<()> {
+        let before = eval_source("x := 1; y := 2")?;
+        let after = execute(parse_program("x := x + 10")?, before.clone())?;
+        assert_eq!(
+            env_diff(&before, &after),
+            vec![(String::from("x"), Some(1), 11)]
+        );
+
+        let after = execute(parse_program("z := 3; y := 2")?, after)?;
+        assert_eq!(
+            env_diff(&before, &after),
+            vec![
+                (String::from("x"), Some(1), 11),
+                (String::from("z"), None, 3)
+            ]
+        );
+        assert!(env_diff(&after, &after).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_stepper() -> Result<()> {
+        let mut stepper = Stepper::new(
+            parse_program("x := 1; y := x + 1; x := x * 10")?,
+            HashMap::new(),
+        );
+        assert_eq!(stepper.peek(), Some(&parse_program("x := 1")?));
+
+        let env = stepper.next().unwrap()?;
+        assert_env_eq(&env, &[("x", 1)]);
+        assert_eq!(env.get("y"), None);
+        let env = stepper.next().unwrap()?;
+        assert_env_eq(&env, &[("x", 1), ("y", 2)]);
+        let env = stepper.next().unwrap()?;
+        assert_env_eq(&env, &[("x", 10), ("y", 2)]);
+        assert!(stepper.next().is_none());
+        assert_eq!(stepper.env(), &env);
+
+        // エラーの後は進まない
+        let mut stepper = Stepper::new(parse_program("x := 1 / 0; y := 1")?, HashMap::new());
+        assert!(stepper.next().unwrap().is_err());
+        assert!(stepper.next().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_kind() -> Result<()> {
+        let err = eval_source("x := 1; y := x + z").unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&EvalError::UnknownVariable("z".to_string()))
+        );
+        let expr = parse_expression("1 < 0")?;
+        let stmt = Statement::Assert {
+            expr: Box::new(expr.clone()),
+        };
+        let err = execute(stmt, HashMap::new()).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&EvalError::AssertionFailed(expr)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_limit() -> Result<()> {
+        let stmt = parse_program("i := 0; while 1 do i := i + 1")?;
+        let err = execute_with_limit(stmt, HashMap::new(), Some(100)).unwrap_err();
+        assert_eq!(err.to_string(), "loop step limit exceeded");
+
+        // 上限ちょうどまでは回れる。上限はループごとに数える
+        let stmt =
+            parse_program("i := 0; while i < 3 do i := i + 1; j := 0; while j < 3 do j := j + 1")?;
+        let env = execute_with_limit(stmt, HashMap::new(), Some(3))?;
+        assert_env_eq(&env, &[("i", 3), ("j", 3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_gas() -> Result<()> {
+        let stmt = parse_program("i := 0; while i < 10 do i := i + 1")?;
+        let env = execute_with_gas(stmt.clone(), HashMap::new(), 10_000)?;
+        assert_env_eq(&env, &[("i", 10)]);
+
+        let err = execute_with_gas(stmt, HashMap::new(), 5).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&EvalError::OutOfGas));
 
+        // ループの形によらず無限ループも止まる
+        let stmt = parse_program("while 1 do i := 0")?;
+        assert!(execute_with_gas(stmt, HashMap::new(), 10_000).is_err());
         Ok(())
     }
 }
@@ -1,32 +1,331 @@
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    hash::Hash,
+};
+
+use anyhow::Result;
+use rand::Rng;
 
 pub type State = i32;
-pub type NFATransition = HashMap<State, HashMap<char, HashSet<State>>>;
+// 記号の型Sは省略するとcharになる
+pub type NFATransition<S = char> = HashMap<State, HashMap<S, HashSet<State>>>;
 pub type EpsilonTransition = HashMap<State, HashSet<State>>;
-pub type DFATransition = HashMap<State, HashMap<char, State>>;
+pub type DFATransition<S = char> = HashMap<State, HashMap<S, State>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stats {
+    pub states: usize,
+    pub transitions: usize,
+    pub epsilon_transitions: usize,
+    pub deterministic: bool,
+    pub complete: bool,
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct NFA {
-    pub transition: NFATransition,
+pub struct NFA<S: Eq + Hash = char> {
+    pub transition: NFATransition<S>,
     pub epsilon_transition: EpsilonTransition,
     pub start: State,
     pub finals: HashSet<State>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct DFA {
-    pub transition: DFATransition,
+pub struct DFA<S: Eq + Hash = char> {
+    pub transition: DFATransition<S>,
     pub start: State,
     pub finals: HashSet<State>,
 }
 
-impl NFA {
+// NFAを1本ずつ遷移を足して組み立てる。開始状態は指定しなければ0になる
+#[derive(Debug, Clone)]
+pub struct NfaBuilder<S: Eq + Hash = char> {
+    nfa: NFA<S>,
+}
+
+impl<S: Eq + Hash + Clone> Default for NfaBuilder<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Eq + Hash + Clone> NfaBuilder<S> {
+    pub fn new() -> Self {
+        Self {
+            nfa: NFA {
+                transition: HashMap::new(),
+                epsilon_transition: HashMap::new(),
+                start: 0,
+                finals: HashSet::new(),
+            },
+        }
+    }
+
+    pub fn add_transition(&mut self, from: State, sym: S, to: State) -> &mut Self {
+        self.nfa
+            .transition
+            .entry(from)
+            .or_default()
+            .entry(sym)
+            .or_default()
+            .insert(to);
+        self
+    }
+
+    pub fn add_epsilon(&mut self, from: State, to: State) -> &mut Self {
+        self.nfa
+            .epsilon_transition
+            .entry(from)
+            .or_default()
+            .insert(to);
+        self
+    }
+
+    pub fn set_start(&mut self, state: State) -> &mut Self {
+        self.nfa.start = state;
+        self
+    }
+
+    pub fn add_final(&mut self, state: State) -> &mut Self {
+        self.nfa.finals.insert(state);
+        self
+    }
+
+    pub fn build(&self) -> NFA<S> {
+        self.nfa.clone()
+    }
+}
+
+impl<S: Eq + Hash + Clone> NFA<S> {
+    // 遷移に現れる文字の集合
+    pub fn alphabet(&self) -> HashSet<S> {
+        self.transition
+            .values()
+            .flat_map(|trans| trans.keys().cloned())
+            .collect()
+    }
+
+    // 遷移・開始状態・受理状態に現れるすべての状態
+    fn states(&self) -> HashSet<State> {
+        let mut states = HashSet::from([self.start]);
+        states.extend(self.finals.iter().cloned());
+        for (state, trans) in &self.transition {
+            states.insert(*state);
+            states.extend(trans.values().flatten().cloned());
+        }
+        for (state, nexts) in &self.epsilon_transition {
+            states.insert(*state);
+            states.extend(nexts.iter().cloned());
+        }
+        states
+    }
+
+    pub fn stats(&self) -> Stats {
+        let states = self.states();
+        let alphabet = self.alphabet();
+        let epsilon_transitions = self.epsilon_transition.values().map(|s| s.len()).sum();
+        Stats {
+            states: states.len(),
+            transitions: self
+                .transition
+                .values()
+                .flat_map(|trans| trans.values())
+                .map(|nexts| nexts.len())
+                .sum(),
+            epsilon_transitions,
+            deterministic: self.is_deterministic(),
+            complete: states.iter().all(|state| {
+                alphabet.iter().all(|ch| {
+                    self.transition
+                        .get(state)
+                        .and_then(|trans| trans.get(ch))
+                        .is_some_and(|nexts| !nexts.is_empty())
+                })
+            }),
+        }
+    }
+
+    // ε遷移がなく、各(状態, 記号)の遷移先が高々1つなら、部分集合構成法なしでDFAとして扱える
+    pub fn is_deterministic(&self) -> bool {
+        self.epsilon_transition
+            .values()
+            .all(|nexts| nexts.is_empty())
+            && self
+                .transition
+                .values()
+                .flat_map(|trans| trans.values())
+                .all(|nexts| nexts.len() <= 1)
+    }
+
+    // 最小の状態がlowestになるようにすべての状態番号をずらす
+    fn renumbered_from(&self, lowest: State) -> NFA<S> {
+        let offset = lowest - self.states().into_iter().min().unwrap_or(self.start);
+        NFA {
+            transition: self
+                .transition
+                .iter()
+                .map(|(state, trans)| {
+                    let trans = trans
+                        .iter()
+                        .map(|(ch, nexts)| (ch.clone(), nexts.iter().map(|s| s + offset).collect()))
+                        .collect();
+                    (state + offset, trans)
+                })
+                .collect(),
+            epsilon_transition: self
+                .epsilon_transition
+                .iter()
+                .map(|(state, nexts)| (state + offset, nexts.iter().map(|s| s + offset).collect()))
+                .collect(),
+            start: self.start + offset,
+            finals: self.finals.iter().map(|s| s + offset).collect(),
+        }
+    }
+
+    fn max_state(&self) -> State {
+        self.states().into_iter().max().unwrap_or(self.start)
+    }
+
+    // 連接。selfの受理状態からotherの開始状態へε遷移する
+    pub fn concat(&self, other: &NFA<S>) -> NFA<S> {
+        let left = self.clone();
+        let right = other.renumbered_from(left.max_state() + 1);
+        let mut epsilon_transition = left.epsilon_transition;
+        for (state, nexts) in right.epsilon_transition {
+            epsilon_transition.entry(state).or_default().extend(nexts);
+        }
+        for state in &left.finals {
+            epsilon_transition
+                .entry(*state)
+                .or_default()
+                .insert(right.start);
+        }
+        let mut transition = left.transition;
+        transition.extend(right.transition);
+        NFA {
+            transition,
+            epsilon_transition,
+            start: left.start,
+            finals: right.finals,
+        }
+    }
+
+    // 選択。新しい開始状態から両者の開始状態へε遷移する
+    pub fn union(&self, other: &NFA<S>) -> NFA<S> {
+        let start = 0;
+        let left = self.renumbered_from(start + 1);
+        let right = other.renumbered_from(left.max_state() + 1);
+        let mut epsilon_transition = left.epsilon_transition;
+        epsilon_transition.extend(right.epsilon_transition);
+        epsilon_transition.insert(start, HashSet::from([left.start, right.start]));
+        let mut transition = left.transition;
+        transition.extend(right.transition);
+        NFA {
+            transition,
+            epsilon_transition,
+            start,
+            finals: left.finals.union(&right.finals).cloned().collect(),
+        }
+    }
+
+    // クリーネ閉包。新しい開始状態と受理状態で包み、受理状態から開始へ戻るε遷移を張る
+    pub fn star(&self) -> NFA<S> {
+        let start = 0;
+        let body = self.renumbered_from(start + 1);
+        let end = body.max_state() + 1;
+        let mut epsilon_transition = body.epsilon_transition;
+        epsilon_transition.insert(start, HashSet::from([body.start, end]));
+        for state in &body.finals {
+            epsilon_transition
+                .entry(*state)
+                .or_default()
+                .extend([body.start, end]);
+        }
+        NFA {
+            transition: body.transition,
+            epsilon_transition,
+            start,
+            finals: HashSet::from([end]),
+        }
+    }
+
+    // すべての遷移を逆向きにする。開始状態は1つしか持てないので、
+    // 新しい開始状態から元の受理状態へε遷移を張り、元の開始状態を唯一の受理状態にする
+    pub fn reverse(&self) -> NFA<S> {
+        let start = self.max_state() + 1;
+        let mut transition: NFATransition<S> = HashMap::new();
+        for (state, trans) in &self.transition {
+            for (sym, nexts) in trans {
+                for next in nexts {
+                    transition
+                        .entry(*next)
+                        .or_default()
+                        .entry(sym.clone())
+                        .or_default()
+                        .insert(*state);
+                }
+            }
+        }
+        let mut epsilon_transition: EpsilonTransition = HashMap::new();
+        for (state, nexts) in &self.epsilon_transition {
+            for next in nexts {
+                epsilon_transition.entry(*next).or_default().insert(*state);
+            }
+        }
+        epsilon_transition
+            .entry(start)
+            .or_default()
+            .extend(self.finals.iter().cloned());
+        NFA {
+            transition,
+            epsilon_transition,
+            start,
+            finals: HashSet::from([self.start]),
+        }
+    }
+
+    // ε遷移を記号epsによる通常の遷移に置き換える。epsはアルファベットに現れない記号を選ぶこと
+    pub fn with_explicit_epsilon(&self, eps: S) -> NFA<S> {
+        let mut transition = self.transition.clone();
+        for (state, nexts) in &self.epsilon_transition {
+            transition
+                .entry(*state)
+                .or_default()
+                .entry(eps.clone())
+                .or_default()
+                .extend(nexts.iter().cloned());
+        }
+        NFA {
+            transition,
+            epsilon_transition: HashMap::new(),
+            start: self.start,
+            finals: self.finals.clone(),
+        }
+    }
+
+    // with_explicit_epsilonの逆。記号epsによる遷移をε遷移に戻す
+    pub fn with_implicit_epsilon(&self, eps: S) -> NFA<S> {
+        let mut transition = self.transition.clone();
+        let mut epsilon_transition = self.epsilon_transition.clone();
+        for (state, trans) in transition.iter_mut() {
+            if let Some(nexts) = trans.remove(&eps) {
+                epsilon_transition.entry(*state).or_default().extend(nexts);
+            }
+        }
+        transition.retain(|_, trans| !trans.is_empty());
+        NFA {
+            transition,
+            epsilon_transition,
+            start: self.start,
+            finals: self.finals.clone(),
+        }
+    }
+
     // states内の各状態からε遷移した先の集合
     pub fn epsilon_closure_step(&self, states: &HashSet<State>) -> HashSet<State> {
         let mut result = HashSet::new();
         for state in states {
-            if self.epsilon_transition.contains_key(&state) {
-                for s in self.epsilon_transition[&state].clone().into_iter() {
+            if self.epsilon_transition.contains_key(state) {
+                for s in self.epsilon_transition[state].clone().into_iter() {
                     result.insert(s);
                 }
             }
@@ -35,19 +334,22 @@ impl NFA {
         result
     }
 
+    // 新しく追加された状態だけをワークリストでたどる
     pub fn get_epsilon_closure(&self, states: HashSet<State>) -> HashSet<State> {
-        fn inner(nfa: &NFA, states: HashSet<State>) -> HashSet<State> {
-            let new = nfa.epsilon_closure_step(&states);
-            if new == states {
-                return new;
+        let mut worklist: Vec<State> = states.iter().cloned().collect();
+        let mut result = states;
+        while let Some(state) = worklist.pop() {
+            for next in self.epsilon_transition.get(&state).into_iter().flatten() {
+                if result.insert(*next) {
+                    worklist.push(*next);
+                }
             }
-            inner(nfa, new)
         }
-        inner(self, states)
+        result
     }
 
     // currentからsymで遷移した先の集合
-    pub fn transit(&self, current: HashSet<State>, sym: char) -> HashSet<State> {
+    pub fn transit(&self, current: HashSet<State>, sym: S) -> HashSet<State> {
         let mut result = HashSet::new();
         for state in current {
             if self.transition.contains_key(&state) && self.transition[&state].contains_key(&sym) {
@@ -68,41 +370,115 @@ impl NFA {
         false
     }
 
-    pub fn try_accept(&self, code: &str) -> bool {
-        let mut current = HashSet::from([self.start]);
-        for ch in code.chars() {
-            current = self.transit(current, ch);
+    // 開始状態から記号による遷移とε遷移をたどって到達できる状態の集合。開始状態自身も含む
+    pub fn reachable_states(&self) -> HashSet<State> {
+        let mut visited = HashSet::from([self.start]);
+        let mut stack = vec![self.start];
+        while let Some(state) = stack.pop() {
+            let symbol_nexts = self
+                .transition
+                .get(&state)
+                .into_iter()
+                .flat_map(|trans| trans.values().flatten());
+            let epsilon_nexts = self.epsilon_transition.get(&state).into_iter().flatten();
+            for next in symbol_nexts.chain(epsilon_nexts) {
+                if visited.insert(*next) {
+                    stack.push(*next);
+                }
+            }
+        }
+        visited
+    }
+
+    // 開始状態から到達できる受理状態がなければ空
+    pub fn is_empty(&self) -> bool {
+        self.reachable_states().is_disjoint(&self.finals)
+    }
+
+    // 受理するときの状態の列を1つ返す。ε遷移で通った状態も含む。
+    // (状態, 読んだ記号数) を頂点とする幅優先探索なので、ε遷移の少ない経路が選ばれる
+    pub fn accept_path_symbols(&self, symbols: &[S]) -> Option<Vec<State>> {
+        let start = (self.start, 0);
+        let mut parent: HashMap<(State, usize), (State, usize)> = HashMap::new();
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        while let Some((state, pos)) = queue.pop_front() {
+            if pos == symbols.len() && self.finals.contains(&state) {
+                let mut path = vec![state];
+                let mut current = (state, pos);
+                while let Some(prev) = parent.get(&current) {
+                    path.push(prev.0);
+                    current = *prev;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            let epsilon_nexts = self
+                .epsilon_transition
+                .get(&state)
+                .into_iter()
+                .flatten()
+                .map(|next| (*next, pos));
+            let symbol_nexts = symbols
+                .get(pos)
+                .and_then(|sym| self.transition.get(&state)?.get(sym))
+                .into_iter()
+                .flatten()
+                .map(|next| (*next, pos + 1));
+            for next in epsilon_nexts.chain(symbol_nexts) {
+                if visited.insert(next) {
+                    parent.insert(next, (state, pos));
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    // 記号列を受理するか
+    pub fn try_accept_symbols(&self, symbols: &[S]) -> bool {
+        let mut current = self.get_epsilon_closure(HashSet::from([self.start]));
+        for sym in symbols {
+            current = self.transit(current, sym.clone());
         }
         self.is_final(current)
     }
+}
 
-    pub fn to_dfa(&self) -> DFA {
-        let mut new_states: Vec<HashSet<State>> =
-            vec![self.get_epsilon_closure(HashSet::from([self.start]))];
-        let mut trans_dict: DFATransition = HashMap::new();
+// 部分集合構成法は記号の順に状態へ番号を付けるのでOrdを要求する
+impl<S: Eq + Hash + Clone + Ord> NFA<S> {
+    // 部分集合構成法。DFAのアルファベットはNFAの遷移に現れる文字に限られ、
+    // それ以外の文字はNFAと同様に受理されない。
+    // 状態集合はBTreeSetにしてハッシュ表で番号を引き、文字は昇順に調べるので番号付けは決定的になる
+    pub fn to_dfa(&self) -> DFA<S> {
+        let start: BTreeSet<State> = self
+            .get_epsilon_closure(HashSet::from([self.start]))
+            .into_iter()
+            .collect();
+        let mut new_states: Vec<BTreeSet<State>> = vec![start.clone()];
+        let mut state_ids: HashMap<BTreeSet<State>, State> = HashMap::from([(start, 0)]);
+        let mut trans_dict: DFATransition<S> = HashMap::new();
         let mut src = 0;
 
-        let alphabet: HashSet<char> = self
-            .transition
-            .values()
-            .flat_map(|trans| trans.keys().cloned())
-            .collect();
+        let mut alphabet: Vec<S> = self.alphabet().into_iter().collect();
+        alphabet.sort_unstable();
 
         while src < new_states.len() {
-            let cur = new_states[src].clone();
+            let cur: HashSet<State> = new_states[src].iter().cloned().collect();
             let mut src_trans = HashMap::new();
 
-            for &c in &alphabet {
-                let c_next: HashSet<State> = self.get_epsilon_closure(self.transit(cur.clone(), c));
+            for c in &alphabet {
+                let c_next: BTreeSet<State> = self
+                    .get_epsilon_closure(self.transit(cur.clone(), c.clone()))
+                    .into_iter()
+                    .collect();
 
-                let dest = if let Some(pos) = new_states.iter().position(|state| state == &c_next) {
-                    pos
-                } else {
+                let dest = *state_ids.entry(c_next).or_insert_with_key(|c_next| {
                     new_states.push(c_next.clone());
-                    new_states.len() - 1
-                };
+                    (new_states.len() - 1) as State
+                });
 
-                src_trans.insert(c, dest as State);
+                src_trans.insert(c.clone(), dest);
             }
 
             trans_dict.insert(src as State, src_trans);
@@ -113,45 +489,668 @@ impl NFA {
             .iter()
             .enumerate()
             .filter_map(|(i, states)| {
-                if self.is_final(states.clone()) {
+                if states.iter().any(|state| self.finals.contains(state)) {
+                    Some(i as State)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        DFA {
+            transition: trans_dict,
+            start: 0,
+            finals,
+        }
+    }
+}
+
+impl NFA {
+    pub fn try_accept(&self, code: &str) -> bool {
+        self.try_accept_symbols(&code.chars().collect::<Vec<_>>())
+    }
+
+    pub fn accept_path(&self, code: &str) -> Option<Vec<State>> {
+        self.accept_path_symbols(&code.chars().collect::<Vec<_>>())
+    }
+}
+
+impl<S: Eq + Hash + Clone> DFA<S> {
+    // 遷移に現れる文字の集合
+    pub fn alphabet(&self) -> HashSet<S> {
+        self.transition
+            .values()
+            .flat_map(|trans| trans.keys().cloned())
+            .collect()
+    }
+
+    pub fn stats(&self) -> Stats {
+        let mut states = HashSet::from([self.start]);
+        states.extend(self.finals.iter().cloned());
+        for (state, trans) in &self.transition {
+            states.insert(*state);
+            states.extend(trans.values().cloned());
+        }
+        let alphabet = self.alphabet();
+        Stats {
+            states: states.len(),
+            transitions: self.transition.values().map(|trans| trans.len()).sum(),
+            epsilon_transitions: 0,
+            deterministic: true,
+            complete: states.iter().all(|state| {
+                self.transition
+                    .get(state)
+                    .is_some_and(|trans| alphabet.iter().all(|ch| trans.contains_key(ch)))
+            }),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let mut visited = HashSet::from([self.start]);
+        let mut stack = vec![self.start];
+        while let Some(state) = stack.pop() {
+            if self.finals.contains(&state) {
+                return false;
+            }
+            for next in self
+                .transition
+                .get(&state)
+                .into_iter()
+                .flat_map(|t| t.values())
+            {
+                if visited.insert(*next) {
+                    stack.push(*next);
+                }
+            }
+        }
+        true
+    }
+
+    // 記号列を受理するか
+    pub fn try_accept_symbols(&self, symbols: &[S]) -> bool {
+        let mut current = self.start;
+        for sym in symbols {
+            if let Some(next_state) = self
+                .transition
+                .get(&current)
+                .and_then(|trans| trans.get(sym))
+            {
+                current = *next_state;
+            } else {
+                return false;
+            }
+        }
+        self.finals.contains(&current)
+    }
+
+    // 開始状態から到達できない状態と、受理状態に到達できない状態を取り除く
+    pub fn trim(&self) -> DFA<S> {
+        let mut reachable = HashSet::from([self.start]);
+        let mut stack = vec![self.start];
+        while let Some(state) = stack.pop() {
+            for next in self
+                .transition
+                .get(&state)
+                .into_iter()
+                .flat_map(|t| t.values())
+            {
+                if reachable.insert(*next) {
+                    stack.push(*next);
+                }
+            }
+        }
+
+        let mut reverse: HashMap<State, HashSet<State>> = HashMap::new();
+        for (state, trans) in &self.transition {
+            for next in trans.values() {
+                reverse.entry(*next).or_default().insert(*state);
+            }
+        }
+        let mut productive: HashSet<State> = self.finals.clone();
+        let mut stack: Vec<State> = self.finals.iter().cloned().collect();
+        while let Some(state) = stack.pop() {
+            for prev in reverse.get(&state).into_iter().flatten() {
+                if productive.insert(*prev) {
+                    stack.push(*prev);
+                }
+            }
+        }
+
+        let mut kept: Vec<State> = reachable
+            .intersection(&productive)
+            .filter(|state| **state != self.start)
+            .cloned()
+            .collect();
+        kept.sort();
+        kept.insert(0, self.start);
+        let renumber: HashMap<State, State> = kept
+            .iter()
+            .enumerate()
+            .map(|(i, state)| (*state, i as State))
+            .collect();
+
+        let mut transition: DFATransition<S> = HashMap::new();
+        for state in &kept {
+            let trans = self
+                .transition
+                .get(state)
+                .into_iter()
+                .flatten()
+                .filter_map(|(ch, next)| renumber.get(next).map(|next| (ch.clone(), *next)))
+                .collect();
+            transition.insert(renumber[state], trans);
+        }
+
+        DFA {
+            transition,
+            start: 0,
+            finals: self
+                .finals
+                .iter()
+                .filter_map(|state| renumber.get(state).cloned())
+                .collect(),
+        }
+    }
+
+    // 未定義の遷移をすべて1つの死状態へ向けた、アルファベット上で完全なDFA
+    pub fn completed(&self) -> DFA<S> {
+        let alphabet = self.alphabet();
+        let states: HashSet<State> = self
+            .transition
+            .iter()
+            .flat_map(|(state, trans)| std::iter::once(*state).chain(trans.values().cloned()))
+            .chain(std::iter::once(self.start))
+            .chain(self.finals.iter().cloned())
+            .collect();
+        let dead = states.iter().max().cloned().unwrap_or(self.start) + 1;
+
+        let mut transition = self.transition.clone();
+        let mut uses_dead = false;
+        for state in &states {
+            let trans = transition.entry(*state).or_default();
+            for ch in &alphabet {
+                trans.entry(ch.clone()).or_insert_with(|| {
+                    uses_dead = true;
+                    dead
+                });
+            }
+        }
+        if uses_dead {
+            transition.insert(dead, alphabet.iter().map(|ch| (ch.clone(), dead)).collect());
+        }
+
+        DFA {
+            transition,
+            start: self.start,
+            finals: self.finals.clone(),
+        }
+    }
+
+    // 両者の受理する言語が等しいかどうか。対称差の積オートマトンが受理状態を持たないことで判定する
+    pub fn equivalent(&self, other: &DFA<S>) -> bool {
+        self.product(other, |left, right| left != right)
+            .finals
+            .is_empty()
+    }
+
+    // 直積構成による積オートマトン。両方が受理する文字列のみを受理する
+    pub fn intersect(&self, other: &DFA<S>) -> DFA<S> {
+        self.product(other, |left, right| left && right)
+    }
+
+    // 直積構成による和オートマトン。どちらかが受理する文字列を受理する
+    pub fn union(&self, other: &DFA<S>) -> DFA<S> {
+        self.product(other, |left, right| left || right)
+    }
+
+    // 状態の組を新しい状態とする。遷移が定義されていない成分はNone(死状態)として扱う
+    fn product(&self, other: &DFA<S>, is_final: fn(bool, bool) -> bool) -> DFA<S> {
+        type Pair = (Option<State>, Option<State>);
+
+        fn step<S: Eq + Hash>(dfa: &DFA<S>, state: Option<State>, ch: &S) -> Option<State> {
+            dfa.transition.get(&state?)?.get(ch).copied()
+        }
+
+        let alphabet: HashSet<S> = self.alphabet().union(&other.alphabet()).cloned().collect();
+
+        let mut pairs: Vec<Pair> = vec![(Some(self.start), Some(other.start))];
+        let mut index: HashMap<Pair, State> = HashMap::from([(pairs[0], 0)]);
+        let mut trans_dict: DFATransition<S> = HashMap::new();
+        let mut src = 0;
+
+        while src < pairs.len() {
+            let (left, right) = pairs[src];
+            let mut src_trans = HashMap::new();
+            for ch in &alphabet {
+                let next = (step(self, left, ch), step(other, right, ch));
+                if next == (None, None) {
+                    continue;
+                }
+                let dest = *index.entry(next).or_insert_with(|| {
+                    pairs.push(next);
+                    (pairs.len() - 1) as State
+                });
+                src_trans.insert(ch.clone(), dest);
+            }
+            trans_dict.insert(src as State, src_trans);
+            src += 1;
+        }
+
+        let finals: HashSet<State> = pairs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (left, right))| {
+                let left_final = left.is_some_and(|state| self.finals.contains(&state));
+                let right_final = right.is_some_and(|state| other.finals.contains(&state));
+                if is_final(left_final, right_final) {
                     Some(i as State)
                 } else {
                     None
                 }
             })
-            .collect();
+            .collect();
+
+        DFA {
+            transition: trans_dict,
+            start: 0,
+            finals,
+        }
+    }
+}
+
+impl<S: Eq + Hash + Clone + Ord> DFA<S> {
+    // Mooreのアルゴリズムによる最小化。区別できない状態を同一視する
+    pub fn minimize(&self) -> DFA<S> {
+        let dfa = self.trim();
+        let mut alphabet: Vec<S> = dfa.alphabet().into_iter().collect();
+        alphabet.sort();
+        let mut states: Vec<State> = dfa.transition.keys().cloned().collect();
+        states.sort();
+
+        let mut class: HashMap<State, usize> = states
+            .iter()
+            .map(|state| (*state, dfa.finals.contains(state) as usize))
+            .collect();
+        let mut class_count = class.values().collect::<HashSet<_>>().len();
+        loop {
+            let mut signatures: HashMap<(usize, Vec<Option<usize>>), usize> = HashMap::new();
+            let mut next_class = HashMap::new();
+            for state in &states {
+                let signature = (
+                    class[state],
+                    alphabet
+                        .iter()
+                        .map(|ch| dfa.transition[state].get(ch).map(|next| class[next]))
+                        .collect(),
+                );
+                let count = signatures.len();
+                next_class.insert(*state, *signatures.entry(signature).or_insert(count));
+            }
+            class = next_class;
+            if signatures.len() == class_count {
+                break;
+            }
+            class_count = signatures.len();
+        }
+
+        let mut transition: DFATransition<S> = HashMap::new();
+        for state in &states {
+            let trans = transition.entry(class[state] as State).or_default();
+            for (ch, next) in &dfa.transition[state] {
+                trans.insert(ch.clone(), class[next] as State);
+            }
+        }
+        DFA {
+            transition,
+            start: class[&dfa.start] as State,
+            finals: dfa
+                .finals
+                .iter()
+                .map(|state| class[state] as State)
+                .collect(),
+        }
+    }
+}
+
+impl DFA {
+    pub fn try_accept(&self, code: &str) -> bool {
+        self.try_accept_symbols(&code.chars().collect::<Vec<_>>())
+    }
+
+    // 受理状態に到達可能な遷移だけを選ぶランダムウォークで、受理される文字列を1つ生成する。
+    // 長さmax_len以下で受理される文字列がなければ、最短の受理される文字列のうち辞書順で最初のものを返す。
+    // Noneになるのは言語が空のときだけ
+    pub fn sample_accepted(&self, rng: &mut impl Rng, max_len: usize) -> Option<String> {
+        // 各状態から受理状態までの最短距離
+        let mut reverse: HashMap<State, Vec<(char, State)>> = HashMap::new();
+        for (state, trans) in &self.transition {
+            for (ch, next) in trans {
+                reverse.entry(*next).or_default().push((*ch, *state));
+            }
+        }
+        let mut distance: HashMap<State, usize> =
+            self.finals.iter().map(|state| (*state, 0)).collect();
+        let mut queue: VecDeque<State> = self.finals.iter().cloned().collect();
+        while let Some(state) = queue.pop_front() {
+            for (_, prev) in reverse.get(&state).into_iter().flatten() {
+                if !distance.contains_key(prev) {
+                    distance.insert(*prev, distance[&state] + 1);
+                    queue.push_back(*prev);
+                }
+            }
+        }
+        let shortest = *distance.get(&self.start)?;
+        if shortest > max_len {
+            // 受理状態に1歩ずつ近づく遷移のうち最小の文字を選んでいく
+            let mut current = self.start;
+            let mut result = String::new();
+            while !self.finals.contains(&current) {
+                let (ch, next) = self.transition[&current]
+                    .iter()
+                    .filter(|(_, next)| distance.get(next) == Some(&(distance[&current] - 1)))
+                    .min()?;
+                result.push(*ch);
+                current = *next;
+            }
+            return Some(result);
+        }
+
+        let mut current = self.start;
+        let mut result = String::new();
+        for remaining in (0..=max_len).rev() {
+            let mut choices: Vec<(char, State)> = self
+                .transition
+                .get(&current)
+                .into_iter()
+                .flatten()
+                .filter(|(_, next)| distance.get(next).is_some_and(|d| *d < remaining))
+                .map(|(ch, next)| (*ch, *next))
+                .collect();
+            choices.sort();
+            let can_stop = self.finals.contains(&current);
+            let options = choices.len() + can_stop as usize;
+            let pick = rng.gen_range(0..options);
+            if pick == choices.len() {
+                break;
+            }
+            let (ch, next) = choices[pick];
+            result.push(ch);
+            current = next;
+        }
+        Some(result)
+    }
+
+    // 長さmax_len以下の受理される文字列を、短い順・辞書順に列挙する
+    pub fn accepted_up_to(&self, max_len: usize) -> Vec<String> {
+        let mut alphabet: Vec<char> = self.alphabet().into_iter().collect();
+        alphabet.sort();
+
+        let mut result = vec![];
+        let mut layer = vec![(self.start, String::new())];
+        for len in 0..=max_len {
+            let mut next_layer = vec![];
+            for (state, string) in layer {
+                if self.finals.contains(&state) {
+                    result.push(string.clone());
+                }
+                if len == max_len {
+                    continue;
+                }
+                for ch in &alphabet {
+                    if let Some(next) = self.transition.get(&state).and_then(|t| t.get(ch)) {
+                        let mut next_string = string.clone();
+                        next_string.push(*ch);
+                        next_layer.push((*next, next_string));
+                    }
+                }
+            }
+            layer = next_layer;
+        }
+        result
+    }
+
+    // 遷移表をCSVにする。1行目は記号、各行は状態で、セルは遷移先(なければ空)。
+    // 状態の列には開始状態なら'>'、受理状態なら'*'を前に付ける
+    pub fn to_table(&self) -> String {
+        let mut alphabet: Vec<char> = self.alphabet().into_iter().collect();
+        alphabet.sort();
+        let mut states: BTreeSet<State> = self.transition.keys().cloned().collect();
+        states.extend(self.transition.values().flat_map(|trans| trans.values()));
+        states.insert(self.start);
+        states.extend(self.finals.iter());
+
+        let mut header = vec!["state".to_string()];
+        header.extend(alphabet.iter().map(|ch| csv_cell(&ch.to_string())));
+        let mut table = header.join(",") + "\n";
+        for state in states {
+            let mut row = String::new();
+            if state == self.start {
+                row.push('>');
+            }
+            if self.finals.contains(&state) {
+                row.push('*');
+            }
+            row.push_str(&state.to_string());
+            for ch in &alphabet {
+                row.push(',');
+                if let Some(next) = self.transition.get(&state).and_then(|t| t.get(ch)) {
+                    row.push_str(&next.to_string());
+                }
+            }
+            table.push_str(&row);
+            table.push('\n');
+        }
+        table
+    }
+
+    // to_tableの逆。遷移のない状態は遷移表に含めない
+    pub fn from_table(csv: &str) -> Result<DFA> {
+        let mut rows = parse_csv(csv)?.into_iter();
+        let Some(header) = rows.next() else {
+            anyhow::bail!("empty table");
+        };
+        let alphabet = header[1..]
+            .iter()
+            .map(|cell| {
+                let mut chars = cell.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => Ok(ch),
+                    _ => anyhow::bail!("symbol {:?} is not a single character", cell),
+                }
+            })
+            .collect::<Result<Vec<char>>>()?;
 
-        DFA {
-            transition: trans_dict,
-            start: 0,
-            finals,
+        let mut transition: DFATransition = HashMap::new();
+        let mut start = None;
+        let mut finals = HashSet::new();
+        for row in rows {
+            if row.len() != header.len() {
+                anyhow::bail!(
+                    "expected {} cells but got {}: {:?}",
+                    header.len(),
+                    row.len(),
+                    row
+                );
+            }
+            let mut name = row[0].as_str();
+            let is_start = name.starts_with('>');
+            name = name.trim_start_matches('>');
+            let is_final = name.starts_with('*');
+            name = name.trim_start_matches('*');
+            let state: State = name
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid state {:?}", row[0]))?;
+            if is_start && start.replace(state).is_some() {
+                anyhow::bail!("multiple start states");
+            }
+            if is_final {
+                finals.insert(state);
+            }
+            for (ch, cell) in alphabet.iter().zip(&row[1..]) {
+                if cell.is_empty() {
+                    continue;
+                }
+                let next: State = cell
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("invalid state {:?}", cell))?;
+                transition.entry(state).or_default().insert(*ch, next);
+            }
         }
+        let start = start.ok_or_else(|| anyhow::anyhow!("no start state"))?;
+        Ok(DFA {
+            transition,
+            start,
+            finals,
+        })
     }
 }
 
-impl DFA {
-    pub fn try_accept(&self, code: &str) -> bool {
-        let mut current = self.start;
-        for ch in code.chars() {
-            if let Some(next_state) = self
-                .transition
-                .get(&current)
-                .and_then(|trans| trans.get(&ch))
-            {
-                current = *next_state;
-            } else {
-                return false;
+// カンマや引用符、改行を含むセルは引用符で囲む
+fn csv_cell(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+// 引用符で囲まれたセルの中では改行やカンマもセルの一部として読む。空行は飛ばす
+fn parse_csv(csv: &str) -> Result<Vec<Vec<String>>> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = csv.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    cell.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if cell.is_empty() => quoted = true,
+            _ if quoted => cell.push(ch),
+            ',' => row.push(std::mem::take(&mut cell)),
+            '\r' => {}
+            '\n' => {
+                if !row.is_empty() || !cell.is_empty() {
+                    row.push(std::mem::take(&mut cell));
+                    rows.push(std::mem::take(&mut row));
+                }
             }
+            _ => cell.push(ch),
         }
-        self.finals.contains(&current)
     }
+    if quoted {
+        anyhow::bail!("unterminated quoted cell");
+    }
+    if !row.is_empty() || !cell.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    Ok(rows)
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::{HashMap, HashSet};
+    use std::{
+        collections::{HashMap, HashSet},
+        time::Instant,
+    };
+
+    use anyhow::Result;
+    use proptest::prelude::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::{NfaBuilder, State, Stats, DFA, NFA};
+
+    // 文字列wordのみを受理するNFA
+    fn word_nfa(word: &str) -> NFA {
+        NFA {
+            transition: word
+                .chars()
+                .enumerate()
+                .map(|(i, ch)| {
+                    (
+                        i as State,
+                        HashMap::from([(ch, HashSet::from([i as State + 1]))]),
+                    )
+                })
+                .collect(),
+            epsilon_transition: HashMap::new(),
+            start: 0,
+            finals: HashSet::from([word.chars().count() as State]),
+        }
+    }
+
+    fn nfa3() -> NFA {
+        NFA {
+            transition: vec![
+                (0, 'a', 1),
+                (0, 'a', 2),
+                (1, 'b', 3),
+                (2, 'b', 3),
+                (3, 'c', 4),
+            ]
+            .into_iter()
+            .fold(HashMap::new(), |mut acc, (state, ch, next_state)| {
+                acc.entry(state)
+                    .or_insert_with(HashMap::new)
+                    .entry(ch)
+                    .or_insert_with(HashSet::new)
+                    .insert(next_state);
+                acc
+            }),
+            epsilon_transition: vec![(0, 1), (0, 2)].into_iter().fold(
+                HashMap::new(),
+                |mut acc, (state, next_state)| {
+                    acc.entry(state)
+                        .or_insert_with(HashSet::new)
+                        .insert(next_state);
+                    acc
+                },
+            ),
+            start: 0,
+            finals: vec![4].into_iter().collect(),
+        }
+    }
+
+    fn build_dfa(transitions: Vec<(State, char, State)>, start: State, finals: Vec<State>) -> DFA {
+        DFA {
+            transition: transitions.into_iter().fold(
+                HashMap::new(),
+                |mut acc, (state, ch, next_state)| {
+                    acc.entry(state)
+                        .or_insert_with(HashMap::new)
+                        .insert(ch, next_state);
+                    acc
+                },
+            ),
+            start,
+            finals: finals.into_iter().collect(),
+        }
+    }
+
+    // 末尾がaである文字列
+    fn ends_with_a() -> DFA {
+        build_dfa(
+            vec![(0, 'a', 1), (0, 'b', 0), (1, 'a', 1), (1, 'b', 0)],
+            0,
+            vec![1],
+        )
+    }
 
-    use super::{DFA, NFA};
+    // bを含む文字列
+    fn contains_b() -> DFA {
+        build_dfa(
+            vec![(0, 'a', 0), (0, 'b', 1), (1, 'a', 1), (1, 'b', 1)],
+            0,
+            vec![1],
+        )
+    }
 
     #[test]
     fn test_nfa1() {
@@ -183,6 +1182,39 @@ mod tests {
         assert!(!nfa.try_accept("abcd"));
     }
 
+    #[test]
+    fn test_nfa_builder() {
+        // test_nfa1と同じNFAをビルダーで組み立てる
+        let nfa = NfaBuilder::new()
+            .add_transition(0, 'a', 1)
+            .add_transition(1, 'b', 2)
+            .add_transition(2, 'c', 3)
+            .add_epsilon(0, 1)
+            .add_epsilon(1, 2)
+            .add_epsilon(2, 3)
+            .set_start(0)
+            .add_final(3)
+            .build();
+        let expected = NFA {
+            transition: HashMap::from([
+                (0, HashMap::from([('a', HashSet::from([1]))])),
+                (1, HashMap::from([('b', HashSet::from([2]))])),
+                (2, HashMap::from([('c', HashSet::from([3]))])),
+            ]),
+            epsilon_transition: HashMap::from([
+                (0, HashSet::from([1])),
+                (1, HashSet::from([2])),
+                (2, HashSet::from([3])),
+            ]),
+            start: 0,
+            finals: HashSet::from([3]),
+        };
+        assert_eq!(nfa, expected);
+        assert!(nfa.try_accept("abc"));
+        assert!(nfa.try_accept("ab"));
+        assert!(!nfa.try_accept("abcd"));
+    }
+
     #[test]
     fn test_nfa2() {
         let nfa = NFA {
@@ -276,4 +1308,580 @@ mod tests {
         assert!(!dfa.try_accept("ab"));
         assert!(!dfa.try_accept("abcd"));
     }
+
+    #[test]
+    fn test_dfa_intersect() {
+        let dfa = ends_with_a().intersect(&contains_b());
+        assert!(dfa.try_accept("ba"));
+        assert!(dfa.try_accept("bba"));
+        assert!(dfa.try_accept("abaa"));
+        assert!(!dfa.try_accept(""));
+        assert!(!dfa.try_accept("a"));
+        assert!(!dfa.try_accept("aaa"));
+        assert!(!dfa.try_accept("ab"));
+    }
+
+    #[test]
+    fn test_dfa_union() {
+        // "ab"のみを受理する
+        let ab = build_dfa(vec![(0, 'a', 1), (1, 'b', 2)], 0, vec![2]);
+        // "c"を1個以上並べた文字列
+        let cs = build_dfa(vec![(0, 'c', 1), (1, 'c', 1)], 0, vec![1]);
+        let dfa = ab.union(&cs);
+        assert!(dfa.try_accept("ab"));
+        assert!(dfa.try_accept("c"));
+        assert!(dfa.try_accept("ccc"));
+        assert!(!dfa.try_accept(""));
+        assert!(!dfa.try_accept("abc"));
+        assert!(!dfa.try_accept("ca"));
+
+        let dfa = ends_with_a().union(&contains_b());
+        assert!(dfa.try_accept("a"));
+        assert!(dfa.try_accept("b"));
+        assert!(dfa.try_accept("ab"));
+        assert!(!dfa.try_accept(""));
+    }
+
+    #[test]
+    fn test_dfa_trim() {
+        // 2は受理状態に到達できない死状態、3は開始状態から到達できない
+        let dfa = build_dfa(
+            vec![
+                (0, 'a', 1),
+                (0, 'b', 2),
+                (1, 'a', 1),
+                (2, 'a', 2),
+                (2, 'b', 2),
+                (3, 'a', 1),
+            ],
+            0,
+            vec![1],
+        );
+        let trimmed = dfa.trim();
+        assert_eq!(trimmed.transition.len(), 2);
+        assert!(trimmed
+            .transition
+            .values()
+            .flat_map(|trans| trans.values())
+            .all(|state| trimmed.transition.contains_key(state)));
+        for input in ["", "a", "aa", "b", "ab", "ba", "aab"] {
+            assert_eq!(dfa.try_accept(input), trimmed.try_accept(input));
+        }
+    }
+
+    #[test]
+    fn test_dfa_minimize_and_equivalent() {
+        // 0と2は区別できない
+        let dfa = build_dfa(
+            vec![
+                (0, 'a', 1),
+                (0, 'b', 2),
+                (1, 'a', 1),
+                (1, 'b', 2),
+                (2, 'a', 1),
+                (2, 'b', 2),
+            ],
+            0,
+            vec![1],
+        );
+        let minimized = dfa.minimize();
+        assert_eq!(minimized.transition.len(), 2);
+        assert!(dfa.equivalent(&minimized));
+        assert!(minimized.equivalent(&dfa));
+        assert!(dfa.equivalent(&ends_with_a()));
+        assert!(!dfa.equivalent(&contains_b()));
+        assert!(!contains_b().equivalent(&dfa));
+    }
+
+    #[test]
+    fn test_dfa_accepted_up_to() {
+        // a(b|ε)
+        let dfa = build_dfa(vec![(0, 'a', 1), (1, 'b', 2)], 0, vec![1, 2]);
+        assert_eq!(dfa.accepted_up_to(0), Vec::<String>::new());
+        assert_eq!(dfa.accepted_up_to(1), vec!["a"]);
+        assert_eq!(dfa.accepted_up_to(3), vec!["a", "ab"]);
+
+        assert_eq!(ends_with_a().accepted_up_to(2), vec!["a", "aa", "ba"]);
+    }
+
+    #[test]
+    fn test_is_empty() {
+        let nfa = NFA {
+            transition: HashMap::from([(0, HashMap::from([('a', HashSet::from([1]))]))]),
+            epsilon_transition: HashMap::from([(1, HashSet::from([0]))]),
+            start: 0,
+            finals: HashSet::from([2]),
+        };
+        assert!(nfa.is_empty());
+
+        let nfa: NFA = NFA {
+            transition: HashMap::new(),
+            epsilon_transition: HashMap::from([(0, HashSet::from([1]))]),
+            start: 0,
+            finals: HashSet::from([1]),
+        };
+        assert!(!nfa.is_empty());
+
+        let dfa = build_dfa(vec![(0, 'a', 1), (2, 'b', 3)], 0, vec![3]);
+        assert!(dfa.is_empty());
+
+        let dfa = build_dfa(vec![], 0, vec![0]);
+        assert!(!dfa.is_empty());
+        assert!(!ends_with_a().is_empty());
+    }
+
+    #[test]
+    fn test_nfa_accepts_empty_via_epsilon() {
+        let nfa = NFA {
+            transition: HashMap::from([(2, HashMap::from([('a', HashSet::from([3]))]))]),
+            epsilon_transition: HashMap::from([(0, HashSet::from([1])), (1, HashSet::from([2]))]),
+            start: 0,
+            finals: HashSet::from([2, 3]),
+        };
+        assert!(nfa.try_accept(""));
+        assert!(nfa.try_accept("a"));
+        assert!(!nfa.try_accept("aa"));
+        assert!(nfa.to_dfa().try_accept(""));
+    }
+
+    #[test]
+    fn test_epsilon_closure_long_chain() {
+        let n = 100_000;
+        let nfa = NFA {
+            transition: HashMap::new(),
+            epsilon_transition: (0..n).map(|i| (i, HashSet::from([i + 1]))).collect(),
+            start: 0,
+            finals: HashSet::from([n]),
+        };
+        let closure = nfa.get_epsilon_closure(HashSet::from([0]));
+        assert_eq!(closure, (0..=n).collect());
+        let closure = nfa.get_epsilon_closure(HashSet::from([n - 1]));
+        assert_eq!(closure, HashSet::from([n - 1, n]));
+        assert!(nfa.try_accept(""));
+    }
+
+    #[test]
+    fn test_dfa_sample_accepted() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let dfa = ends_with_a().intersect(&contains_b());
+        for _ in 0..100 {
+            let sample = dfa
+                .sample_accepted(&mut rng, 8)
+                .expect("language is not empty");
+            assert!(sample.len() <= 8);
+            assert!(dfa.try_accept(&sample), "{:?} is not accepted", sample);
+        }
+        assert_eq!(dfa.sample_accepted(&mut rng, 0), Some("ba".to_string()));
+
+        // "ab"のみを受理する
+        let ab = build_dfa(vec![(0, 'a', 1), (1, 'b', 2), (1, 'a', 3)], 0, vec![2]);
+        assert_eq!(ab.sample_accepted(&mut rng, 5), Some("ab".to_string()));
+        // max_len以内に受理される文字列がなくても、空でない言語なら最短のものを返す
+        assert_eq!(ab.sample_accepted(&mut rng, 1), Some("ab".to_string()));
+
+        let empty = build_dfa(vec![(0, 'a', 1)], 0, vec![]);
+        assert_eq!(empty.sample_accepted(&mut rng, 5), None);
+    }
+
+    #[test]
+    fn test_dfa_completed() {
+        let dfa = build_dfa(vec![(0, 'a', 1), (1, 'b', 2)], 0, vec![2]);
+        let completed = dfa.completed();
+        assert_eq!(completed.transition.len(), 4);
+        for trans in completed.transition.values() {
+            assert_eq!(trans.len(), 2);
+        }
+        for input in ["", "a", "ab", "b", "aa", "aba", "abb"] {
+            assert_eq!(dfa.try_accept(input), completed.try_accept(input));
+        }
+
+        // 既に完全なら死状態は追加されない
+        assert_eq!(ends_with_a().completed(), ends_with_a());
+    }
+
+    #[test]
+    fn test_alphabet() {
+        assert_eq!(ends_with_a().alphabet(), HashSet::from(['a', 'b']));
+        let dfa = build_dfa(vec![(0, 'x', 1), (1, 'y', 2), (2, 'x', 0)], 0, vec![2]);
+        assert_eq!(dfa.alphabet(), HashSet::from(['x', 'y']));
+
+        let nfa = NFA {
+            transition: HashMap::from([
+                (0, HashMap::from([('a', HashSet::from([1, 2]))])),
+                (2, HashMap::from([('c', HashSet::from([3]))])),
+            ]),
+            epsilon_transition: HashMap::from([(1, HashSet::from([2]))]),
+            start: 0,
+            finals: HashSet::from([3]),
+        };
+        assert_eq!(nfa.alphabet(), HashSet::from(['a', 'c']));
+    }
+
+    #[test]
+    fn test_nfa_concat() {
+        let nfa = word_nfa("ab").concat(&word_nfa("c"));
+        assert!(nfa.try_accept("abc"));
+        assert!(!nfa.try_accept("ab"));
+        assert!(!nfa.try_accept("c"));
+        assert!(!nfa.try_accept("abcc"));
+
+        let nfa = word_nfa("a").union(&word_nfa("b")).concat(&word_nfa("c"));
+        assert!(nfa.try_accept("ac"));
+        assert!(nfa.try_accept("bc"));
+        assert!(!nfa.try_accept("abc"));
+    }
+
+    #[test]
+    fn test_nfa_union() {
+        let nfa = word_nfa("ab").union(&word_nfa("c"));
+        assert!(nfa.try_accept("ab"));
+        assert!(nfa.try_accept("c"));
+        assert!(!nfa.try_accept("abc"));
+        assert!(!nfa.try_accept(""));
+
+        let nfa = word_nfa("").union(&word_nfa("a"));
+        assert!(nfa.try_accept(""));
+        assert!(nfa.try_accept("a"));
+        assert!(!nfa.try_accept("aa"));
+    }
+
+    #[test]
+    fn test_nfa_star() {
+        let nfa = word_nfa("ab").star();
+        assert!(nfa.try_accept(""));
+        assert!(nfa.try_accept("ab"));
+        assert!(nfa.try_accept("ababab"));
+        assert!(!nfa.try_accept("a"));
+        assert!(!nfa.try_accept("aba"));
+        assert!(nfa.to_dfa().try_accept("abab"));
+
+        let nfa = word_nfa("a").union(&word_nfa("b")).star();
+        assert!(nfa.try_accept("abba"));
+        assert!(!nfa.try_accept("abc"));
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(
+            nfa3().stats(),
+            Stats {
+                states: 5,
+                transitions: 5,
+                epsilon_transitions: 2,
+                deterministic: false,
+                complete: false,
+            }
+        );
+        assert_eq!(
+            ends_with_a().stats(),
+            Stats {
+                states: 2,
+                transitions: 4,
+                epsilon_transitions: 0,
+                deterministic: true,
+                complete: true,
+            }
+        );
+        let dfa = build_dfa(vec![(0, 'a', 1), (1, 'b', 2)], 0, vec![2]);
+        assert!(!dfa.stats().complete);
+        assert!(dfa.completed().stats().complete);
+    }
+
+    // 線形探索で既存の部分集合を探す、以前の部分集合構成法
+    fn to_dfa_linear(nfa: &NFA) -> DFA {
+        let mut new_states = vec![nfa.get_epsilon_closure(HashSet::from([nfa.start]))];
+        let mut transition = HashMap::new();
+        let mut alphabet: Vec<char> = nfa.alphabet().into_iter().collect();
+        alphabet.sort_unstable();
+        let mut src = 0;
+        while src < new_states.len() {
+            let cur = new_states[src].clone();
+            let mut src_trans = HashMap::new();
+            for &c in &alphabet {
+                let c_next = nfa.get_epsilon_closure(nfa.transit(cur.clone(), c));
+                let dest = match new_states.iter().position(|state| state == &c_next) {
+                    Some(pos) => pos,
+                    None => {
+                        new_states.push(c_next);
+                        new_states.len() - 1
+                    }
+                };
+                src_trans.insert(c, dest as State);
+            }
+            transition.insert(src as State, src_trans);
+            src += 1;
+        }
+        let finals = (0..new_states.len())
+            .filter(|&i| nfa.is_final(new_states[i].clone()))
+            .map(|i| i as State)
+            .collect();
+        DFA {
+            transition,
+            start: 0,
+            finals,
+        }
+    }
+
+    // (a|b)*a(a|b){n}。DFAは2^(n+1)状態になる
+    fn large_nfa(n: State) -> NFA {
+        let mut transition = HashMap::from([(
+            0,
+            HashMap::from([('a', HashSet::from([0, 1])), ('b', HashSet::from([0]))]),
+        )]);
+        for state in 1..=n {
+            transition.insert(
+                state,
+                HashMap::from([
+                    ('a', HashSet::from([state + 1])),
+                    ('b', HashSet::from([state + 1])),
+                ]),
+            );
+        }
+        NFA {
+            transition,
+            epsilon_transition: HashMap::new(),
+            start: 0,
+            finals: HashSet::from([n + 1]),
+        }
+    }
+
+    #[test]
+    fn test_to_dfa_large_nfa() {
+        let n = 9;
+        let nfa = large_nfa(n);
+        let dfa = nfa.to_dfa();
+        assert_eq!(dfa, to_dfa_linear(&nfa));
+        assert_eq!(dfa.transition.len(), 1 << (n + 1));
+    }
+
+    // 実行時間の比較は負荷に左右されるので既定では走らせない。cargo test -- --ignoredで確かめる
+    #[test]
+    #[ignore]
+    fn bench_to_dfa_large_nfa() {
+        let nfa = large_nfa(9);
+        let started = Instant::now();
+        nfa.to_dfa();
+        let hashed = started.elapsed();
+        let started = Instant::now();
+        to_dfa_linear(&nfa);
+        let linear = started.elapsed();
+        assert!(hashed < linear, "hashed {:?}, linear {:?}", hashed, linear);
+    }
+
+    #[test]
+    fn test_token_symbols() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        enum Kind {
+            Ident,
+            Comma,
+        }
+
+        // Ident (Comma Ident)*
+        let nfa: NFA<Kind> = NFA {
+            transition: HashMap::from([
+                (0, HashMap::from([(Kind::Ident, HashSet::from([1]))])),
+                (1, HashMap::from([(Kind::Comma, HashSet::from([2]))])),
+                (2, HashMap::from([(Kind::Ident, HashSet::from([1]))])),
+            ]),
+            epsilon_transition: HashMap::new(),
+            start: 0,
+            finals: HashSet::from([1]),
+        };
+        let dfa = nfa.to_dfa().minimize();
+        for (input, expected) in [
+            (vec![Kind::Ident], true),
+            (vec![Kind::Ident, Kind::Comma, Kind::Ident], true),
+            (vec![Kind::Ident, Kind::Comma], false),
+            (vec![Kind::Comma, Kind::Ident], false),
+            (vec![], false),
+        ] {
+            assert_eq!(nfa.try_accept_symbols(&input), expected, "{:?}", input);
+            assert_eq!(dfa.try_accept_symbols(&input), expected, "{:?}", input);
+        }
+        assert_eq!(dfa.alphabet(), HashSet::from([Kind::Ident, Kind::Comma]));
+    }
+
+    #[test]
+    fn test_is_deterministic() {
+        assert!(word_nfa("abc").is_deterministic());
+        assert!(!nfa3().is_deterministic());
+
+        let fan_out = NfaBuilder::new()
+            .add_transition(0, 'a', 1)
+            .add_transition(0, 'a', 2)
+            .add_final(2)
+            .build();
+        assert!(!fan_out.is_deterministic());
+
+        let epsilon = NfaBuilder::new()
+            .add_transition(0, 'a', 1)
+            .add_epsilon(1, 2)
+            .add_final(2)
+            .build();
+        assert!(!epsilon.is_deterministic());
+    }
+
+    #[test]
+    fn test_reverse() {
+        let nfa = word_nfa("ab").union(&nfa3()).concat(&word_nfa("c").star());
+        let reversed = nfa.reverse();
+        let mut words = vec![String::new()];
+        let mut layer = words.clone();
+        for _ in 0..5 {
+            layer = layer
+                .iter()
+                .flat_map(|word| ['a', 'b', 'c'].map(|ch| format!("{}{}", word, ch)))
+                .collect();
+            words.extend(layer.iter().cloned());
+        }
+        let mut accepted = 0;
+        for word in &words {
+            let backwards: String = word.chars().rev().collect();
+            assert_eq!(
+                nfa.try_accept(word),
+                reversed.try_accept(&backwards),
+                "{}",
+                word
+            );
+            accepted += nfa.try_accept(word) as usize;
+        }
+        assert!(accepted > 0);
+        assert!(reversed.try_accept("cccba"));
+        assert!(!reversed.try_accept("abc"));
+    }
+
+    #[test]
+    fn test_accept_path() {
+        let nfa = word_nfa("ab").union(&nfa3()).concat(&word_nfa("c").star());
+        for input in ["ab", "abc", "abcc", "abccc"] {
+            let path = nfa.accept_path(input).expect("should be accepted");
+            assert_eq!(path[0], nfa.start);
+            assert!(nfa.finals.contains(path.last().unwrap()));
+
+            // 隣り合う状態はε遷移か、次の文字による遷移でつながっている
+            let mut chars = input.chars().peekable();
+            for pair in path.windows(2) {
+                let (from, to) = (pair[0], pair[1]);
+                let by_epsilon = nfa
+                    .epsilon_transition
+                    .get(&from)
+                    .is_some_and(|nexts| nexts.contains(&to));
+                let by_char = chars.peek().is_some_and(|ch| {
+                    nfa.transition
+                        .get(&from)
+                        .and_then(|trans| trans.get(ch))
+                        .is_some_and(|nexts| nexts.contains(&to))
+                });
+                if by_char {
+                    chars.next();
+                } else {
+                    assert!(by_epsilon, "{} -> {} on {:?}", from, to, input);
+                }
+            }
+            assert_eq!(chars.next(), None);
+        }
+        assert_eq!(nfa.accept_path("ac"), None);
+        assert_eq!(nfa.accept_path(""), None);
+    }
+
+    #[test]
+    fn test_explicit_epsilon() {
+        let nfa = word_nfa("ab").union(&nfa3()).concat(&word_nfa("c").star());
+        let explicit = nfa.with_explicit_epsilon('#');
+        assert!(explicit.epsilon_transition.is_empty());
+        assert!(explicit.alphabet().contains(&'#'));
+        let implicit = explicit.with_implicit_epsilon('#');
+        assert_eq!(implicit.epsilon_transition, nfa.epsilon_transition);
+        assert!(!implicit.alphabet().contains(&'#'));
+
+        let mut words = vec![String::new()];
+        let mut layer = words.clone();
+        for _ in 0..5 {
+            layer = layer
+                .iter()
+                .flat_map(|word| ['a', 'b', 'c'].map(|ch| format!("{}{}", word, ch)))
+                .collect();
+            words.extend(layer.iter().cloned());
+        }
+        for word in &words {
+            assert_eq!(nfa.try_accept(word), implicit.try_accept(word), "{}", word);
+        }
+        // 明示した形ではεを記号として読ませる必要がある
+        assert!(nfa.try_accept("ab"));
+        assert!(!explicit.try_accept("ab"));
+    }
+
+    #[test]
+    fn test_table_round_trip() -> Result<()> {
+        let dfa = build_dfa(vec![(0, 'a', 1), (1, 'b', 2), (2, 'c', 3)], 0, vec![3]);
+        let table = dfa.to_table();
+        assert_eq!(table, "state,a,b,c\n>0,1,,\n1,,2,\n2,,,3\n*3,,,\n");
+        assert_eq!(DFA::from_table(&table)?, dfa);
+
+        // カンマや引用符も記号として往復できる
+        let dfa = build_dfa(vec![(0, ',', 1), (1, '"', 0), (0, 'x', 0)], 0, vec![0]);
+        assert_eq!(DFA::from_table(&dfa.to_table())?, dfa);
+
+        assert!(DFA::from_table("state,a\n0,1\n").is_err());
+        assert!(DFA::from_table("state,ab\n>0,1\n").is_err());
+        assert!(DFA::from_table("state,a\n>0,1,2\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reachable_states() {
+        // 0 -a-> 1 -ε-> 2、到達できない 5 -b-> 6 -ε-> 2
+        let nfa = NfaBuilder::new()
+            .add_transition(0, 'a', 1)
+            .add_epsilon(1, 2)
+            .add_transition(5, 'b', 6)
+            .add_epsilon(6, 2)
+            .add_final(2)
+            .build();
+        assert_eq!(nfa.reachable_states(), HashSet::from([0, 1, 2]));
+        assert!(!nfa.is_empty());
+
+        let unreachable = NfaBuilder::new()
+            .add_transition(0, 'a', 1)
+            .add_transition(5, 'b', 6)
+            .add_final(6)
+            .build();
+        assert_eq!(unreachable.reachable_states(), HashSet::from([0, 1]));
+        assert!(unreachable.is_empty());
+    }
+
+    // 状態0..5、記号a,bの小さなNFA。ε遷移の閉路や到達できない状態も生成される
+    fn arb_nfa() -> impl Strategy<Value = NFA> {
+        (
+            prop::collection::vec((0..5, prop::sample::select(vec!['a', 'b']), 0..5), 0..12),
+            prop::collection::vec((0..5, 0..5), 0..4),
+            prop::collection::vec(0..5, 0..3),
+        )
+            .prop_map(|(transitions, epsilons, finals)| {
+                let mut builder = NfaBuilder::new();
+                for (from, sym, to) in transitions {
+                    builder.add_transition(from, sym, to);
+                }
+                for (from, to) in epsilons {
+                    builder.add_epsilon(from, to);
+                }
+                for state in finals {
+                    builder.add_final(state);
+                }
+                builder.build()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_to_dfa_preserves_language(
+            nfa in arb_nfa(),
+            inputs in prop::collection::vec("[ab]{0,8}", 1..16),
+        ) {
+            let dfa = nfa.to_dfa();
+            for input in &inputs {
+                prop_assert_eq!(nfa.try_accept(input), dfa.try_accept(input), "{:?}", input);
+            }
+        }
+    }
 }
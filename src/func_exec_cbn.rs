@@ -2,10 +2,14 @@ use std::collections::HashMap;
 
 use anyhow::Result;
 
-use crate::ast::{Expression, Statement};
+use crate::{
+    ast::{Expression, Statement},
+    binop::apply_binop,
+    error::EvalError,
+};
 
-type Environment = HashMap<String, Thunk>;
-type FunctionEnvironment = HashMap<String, Statement>;
+pub type Environment = HashMap<String, Thunk>;
+pub type FunctionEnvironment = HashMap<String, Statement>;
 
 #[derive(Clone, Debug)]
 pub struct Thunk {
@@ -14,22 +18,30 @@ pub struct Thunk {
     func_env: Box<FunctionEnvironment>,
 }
 
+impl Thunk {
+    // 束縛された環境で式を評価して値を得る
+    pub fn force(&self) -> Result<Expression> {
+        evaluate(self.expr.clone(), self.env.clone(), self.func_env.clone())
+    }
+}
+
+#[allow(clippy::boxed_local)]
 pub fn evaluate(
     expr: Box<Expression>,
     env: Box<Environment>,
     func_env: Box<FunctionEnvironment>,
 ) -> Result<Expression> {
     fn make_thunk_list(
-        args: &Vec<Box<Expression>>,
-        env: &Box<Environment>,
-        func_env: &Box<FunctionEnvironment>,
+        args: &[Box<Expression>],
+        env: &Environment,
+        func_env: &FunctionEnvironment,
     ) -> Result<Vec<Thunk>> {
         args.iter()
             .map(|arg| {
                 Ok(Thunk {
                     expr: arg.clone(),
-                    env: env.clone(),
-                    func_env: func_env.clone(),
+                    env: Box::new(env.clone()),
+                    func_env: Box::new(func_env.clone()),
                 })
             })
             .collect()
@@ -46,48 +58,51 @@ pub fn evaluate(
     fn exec_fun(
         func_name: &str,
         args: Vec<Thunk>,
-        func_env: &Box<FunctionEnvironment>,
+        func_env: &FunctionEnvironment,
     ) -> Result<Expression> {
         fn build_environment_from_args(
-            params: &Vec<String>,
+            func_name: &str,
+            params: &[String],
             args: Vec<Thunk>,
         ) -> Result<Environment> {
             if params.len() != args.len() {
-                anyhow::bail!("Wrong number of args: {:?} for {:?}", args, params);
+                anyhow::bail!(EvalError::Arity {
+                    name: func_name.to_string(),
+                    expected: params.len(),
+                    found: args.len(),
+                });
             }
             let mut env = HashMap::new();
-            for (param, arg) in params.iter().zip(args.into_iter()) {
+            for (param, arg) in params.iter().zip(args) {
                 env.insert(param.clone(), arg);
             }
             Ok(env)
         }
         let stmt = func_env
             .get(func_name)
-            .ok_or_else(|| anyhow::anyhow!("Unknown function: {}", func_name))?;
+            .ok_or_else(|| EvalError::UnknownFunction(func_name.to_string()))?;
         let (params, body) = match stmt {
             Statement::FuncDef { params, body } => (params.clone(), body.clone()),
             _ => anyhow::bail!("Expected to Statement::FuncDef {:?}", stmt),
         };
-        let mut env = build_environment_from_args(&params, args)?;
+        let mut env = build_environment_from_args(func_name, &params, args)?;
         let binding = Box::new(env.clone());
         env.insert(
             String::from("return"),
             Thunk {
                 expr: Box::new(Expression::Int { value: 0 }),
                 env: binding,
-                func_env: func_env.clone(),
+                func_env: Box::new(func_env.clone()),
             },
         );
-        let _ = execute(body, Box::new(env.clone()), func_env.clone())?;
-        Ok(eval_thunk(env.get("return").ok_or_else(|| {
-            anyhow::anyhow!("Expected to return value")
-        })?)?)
+        let env = execute(body, Box::new(env), Box::new(func_env.clone()))?;
+        eval_thunk(env.get("return").ok_or(EvalError::MissingReturn)?)
     }
 
     match *expr {
         Expression::Var { ref name } => {
             Ok(eval_thunk(env.get(name).ok_or_else(|| {
-                anyhow::anyhow!("Unknown variable: {}", name)
+                EvalError::UnknownVariable(name.clone())
             })?)?)
         }
         Expression::BinExp {
@@ -100,36 +115,21 @@ pub fn evaluate(
             let left_value = if let Expression::Int { value } = left {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", left);
+                anyhow::bail!(EvalError::TypeMismatch(left));
             };
             let right_value = if let Expression::Int { value } = right {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", right);
+                anyhow::bail!(EvalError::TypeMismatch(right));
             };
-            match op.as_str() {
-                "+" => Ok(Expression::Int {
-                    value: left_value + right_value,
-                }),
-                "-" => Ok(Expression::Int {
-                    value: left_value - right_value,
-                }),
-                "*" => Ok(Expression::Int {
-                    value: left_value * right_value,
-                }),
-                "/" => Ok(Expression::Int {
-                    value: left_value / right_value,
-                }),
-                ">" => Ok(Expression::Int {
-                    value: if left_value > right_value { 1 } else { 0 },
-                }),
-                "<" => Ok(Expression::Int {
-                    value: if left_value < right_value { 1 } else { 0 },
-                }),
-                _ => anyhow::bail!("Unknown op: {}", op),
-            }
+            Ok(Expression::Int {
+                value: apply_binop(op, left_value, right_value)?,
+            })
         }
         Expression::Int { value } => Ok(Expression::Int { value }),
+        Expression::Bool { value } => Ok(Expression::Int {
+            value: value.into(),
+        }),
         Expression::Call { ref name, ref args } => {
             exec_fun(name, make_thunk_list(args, &env, &func_env)?, &func_env)
         }
@@ -147,7 +147,7 @@ pub fn execute(
             let cond_value = if let Expression::Int { value } = cond {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", cond);
+                anyhow::bail!(EvalError::TypeMismatch(cond));
             };
             if cond_value != 0 {
                 execute(then, env.clone(), func_env.clone())
@@ -217,7 +217,9 @@ mod tests {
 
     use std::collections::HashMap;
 
-    use super::define_function;
+    use crate::parser::parse_program_with_functions;
+
+    use super::{define_function, execute};
 
     #[test]
     fn test_func_exec_cbv1() -> Result<()> {
@@ -337,4 +339,34 @@ mod tests {
         assert_eq!(result, Expression::Int { value: 55 });
         Ok(())
     }
+
+    #[test]
+    fn test_division_by_zero() -> Result<()> {
+        let (stmt, func_env) =
+            parse_program_with_functions("def inv(n) { return := 100 / n }; x := inv(0)")?;
+        let err = execute(Box::new(stmt), Box::default(), Box::new(func_env)).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero");
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_and_multi_arg_functions() -> Result<()> {
+        let (stmt, func_env) = parse_program_with_functions(
+            "
+            def answer() { return := 42 };
+            def sub(a, b) { return := a - b };
+            x := sub(answer(), 2);
+            y := answer()
+            ",
+        )?;
+        let env = execute(Box::new(stmt), Box::default(), Box::new(func_env))?;
+        assert_eq!(env["x"].force()?, Expression::Int { value: 40 });
+        assert_eq!(env["y"].force()?, Expression::Int { value: 42 });
+
+        let (stmt, func_env) =
+            parse_program_with_functions("def sub(a, b) { return := a - b }; x := sub(1)")?;
+        let err = execute(Box::new(stmt), Box::default(), Box::new(func_env)).unwrap_err();
+        assert_eq!(err.to_string(), "sub expects 2 arguments but got 1");
+        Ok(())
+    }
 }
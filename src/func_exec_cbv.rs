@@ -2,44 +2,63 @@ use std::collections::HashMap;
 
 use anyhow::Result;
 
-use crate::ast::{Expression, Statement};
+use crate::{
+    ast::{Expression, Statement},
+    binop::apply_binop,
+    error::EvalError,
+    exec::{count_iteration, restore_binding},
+};
 
-type Environment = HashMap<String, Expression>;
-type FunctionEnvironment = HashMap<String, Statement>;
+pub type Environment = HashMap<String, Expression>;
+pub type FunctionEnvironment = HashMap<String, Statement>;
 
 pub fn evaluate(
     expr: &Expression,
     env: &Environment,
     func_env: &FunctionEnvironment,
+) -> Result<Expression> {
+    evaluate_with_limit(expr, env, func_env, None)
+}
+
+// limitは関数本体の中のループにも適用する
+pub fn evaluate_with_limit(
+    expr: &Expression,
+    env: &Environment,
+    func_env: &FunctionEnvironment,
+    limit: Option<usize>,
 ) -> Result<Expression> {
     fn evaluate_list(
-        args: &Vec<Box<Expression>>,
+        args: &[Box<Expression>],
         env: &Environment,
         func_env: &FunctionEnvironment,
+        limit: Option<usize>,
     ) -> Result<Vec<Expression>> {
         args.iter()
-            .map(|arg| evaluate(arg, env, func_env))
+            .map(|arg| evaluate_with_limit(arg, env, func_env, limit))
             .collect()
     }
 
     fn exec_fun(
         func_name: &str,
-        args: &Vec<Expression>,
+        args: Vec<Expression>,
         func_env: &FunctionEnvironment,
+        limit: Option<usize>,
     ) -> Result<Expression> {
+        // 引数のない関数はreturn以外に何も束縛されない空の環境で実行する
         fn build_environment_from_args(
-            params: &Vec<String>,
+            func_name: &str,
+            params: &[String],
             args: &Vec<Expression>,
         ) -> Result<Environment> {
             if params.len() != args.len() {
-                anyhow::bail!(
-                    "The number of arguments is not matched. params: {:?}, args: {:?}",
-                    params,
-                    args
-                );
+                anyhow::bail!(EvalError::Arity {
+                    name: func_name.to_string(),
+                    expected: params.len(),
+                    found: args.len(),
+                });
             }
             let mut env = HashMap::new();
-            for (param, arg) in params.into_iter().zip(args.into_iter()) {
+            for (param, arg) in params.iter().zip(args) {
                 env.insert(param.to_string(), arg.clone());
             }
             Ok(env)
@@ -47,60 +66,105 @@ pub fn evaluate(
 
         let stmt = func_env
             .get(func_name)
-            .ok_or_else(|| anyhow::anyhow!("Unknown function: {}", func_name))?;
+            .ok_or_else(|| EvalError::UnknownFunction(func_name.to_string()))?;
         let func = match stmt {
             Statement::FuncDef { params, body } => (params, body),
             _ => anyhow::bail!("Expected to Statement::FuncDef {:?}", stmt),
         };
-        let mut env = build_environment_from_args(func.0, args)?;
-        env.insert(String::from("return"), Expression::Int { value: 0 });
-        let env = execute(&*func.1, &env, func_env)?;
-        env.get("return")
-            .cloned()
-            .ok_or_else(|| anyhow::anyhow!("Expected to return value"))
+        // 自分自身への末尾呼び出しは再帰せず、引数を差し替えて本体を実行し直す
+        let mut args = args;
+        loop {
+            let mut env = build_environment_from_args(func_name, func.0, &args)?;
+            env.insert(String::from("return"), Expression::Int { value: 0 });
+            match execute_tail(func.1, &env, func_env, func_name, limit)? {
+                Tail::Done(env) => {
+                    return env
+                        .get("return")
+                        .cloned()
+                        .ok_or_else(|| EvalError::MissingReturn.into())
+                }
+                Tail::Call(next_args) => args = next_args,
+            }
+        }
     }
 
     match expr {
-        Expression::Var { name } => Ok(env[name].clone()),
+        Expression::Var { name } => env
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EvalError::UnknownVariable(name.clone()).into()),
         Expression::BinExp { op, lhs, rhs } => {
-            let left = evaluate(&*lhs, env, func_env)?;
-            let right = evaluate(&*rhs, env, func_env)?;
+            let left = evaluate_with_limit(lhs, env, func_env, limit)?;
+            let right = evaluate_with_limit(rhs, env, func_env, limit)?;
             let left_value = if let Expression::Int { value } = left {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", left);
+                anyhow::bail!(EvalError::TypeMismatch(left));
             };
             let right_value = if let Expression::Int { value } = right {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", right);
+                anyhow::bail!(EvalError::TypeMismatch(right));
             };
-            match op.as_str() {
-                "+" => Ok(Expression::Int {
-                    value: left_value + right_value,
-                }),
-                "-" => Ok(Expression::Int {
-                    value: left_value - right_value,
-                }),
-                "*" => Ok(Expression::Int {
-                    value: left_value * right_value,
-                }),
-                "/" => Ok(Expression::Int {
-                    value: left_value / right_value,
-                }),
-                ">" => Ok(Expression::Int {
-                    value: if left_value > right_value { 1 } else { 0 },
-                }),
-                "<" => Ok(Expression::Int {
-                    value: if left_value < right_value { 1 } else { 0 },
-                }),
-                _ => anyhow::bail!("Unknown op: {}", op),
-            }
+            Ok(Expression::Int {
+                value: apply_binop(op, left_value, right_value)?,
+            })
         }
         Expression::Int { value } => Ok(Expression::Int { value: *value }),
-        Expression::Call { name, args } => {
-            exec_fun(&name, &evaluate_list(args, env, func_env)?, func_env)
+        Expression::Bool { value } => Ok(Expression::Int {
+            value: (*value).into(),
+        }),
+        Expression::Call { name, args } => exec_fun(
+            name,
+            evaluate_list(args, env, func_env, limit)?,
+            func_env,
+            limit,
+        ),
+    }
+}
+
+enum Tail {
+    Done(Environment),
+    Call(Vec<Expression>),
+}
+
+// 関数本体を実行する。末尾位置(Sequenceの最後の文、Ifの各枝)にある
+// `return := func_name(...)` は実行せず、評価した引数をTail::Callとして返す
+fn execute_tail(
+    stmt: &Statement,
+    env: &Environment,
+    func_env: &FunctionEnvironment,
+    func_name: &str,
+    limit: Option<usize>,
+) -> Result<Tail> {
+    match stmt {
+        Statement::Assign { name, expr } if name == "return" => match &**expr {
+            Expression::Call { name, args } if name == func_name => {
+                let args = args
+                    .iter()
+                    .map(|arg| evaluate_with_limit(arg, env, func_env, limit))
+                    .collect::<Result<_>>()?;
+                Ok(Tail::Call(args))
+            }
+            _ => execute_with_limit(stmt, env, func_env, limit).map(Tail::Done),
+        },
+        Statement::If { cond, then, els } => match evaluate_with_limit(cond, env, func_env, limit)?
+        {
+            Expression::Int { value } if value != 0 => {
+                execute_tail(then, env, func_env, func_name, limit)
+            }
+            Expression::Int { .. } => execute_tail(els, env, func_env, func_name, limit),
+            cond => anyhow::bail!(EvalError::TypeMismatch(cond)),
+        },
+        Statement::Sequence { stmts } if !stmts.is_empty() => {
+            let (last, init) = stmts.split_last().unwrap();
+            let mut current_env = env.clone();
+            for stmt in init {
+                current_env = execute_with_limit(stmt, &current_env, func_env, limit)?;
+            }
+            execute_tail(last, &current_env, func_env, func_name, limit)
         }
+        _ => execute_with_limit(stmt, env, func_env, limit).map(Tail::Done),
     }
 }
 
@@ -108,33 +172,77 @@ pub fn execute(
     stmt: &Statement,
     env: &Environment,
     func_env: &FunctionEnvironment,
+) -> Result<Environment> {
+    execute_with_limit(stmt, env, func_env, None)
+}
+
+// limitを超えて回ったループがあればエラーにする
+pub fn execute_with_limit(
+    stmt: &Statement,
+    env: &Environment,
+    func_env: &FunctionEnvironment,
+    limit: Option<usize>,
 ) -> Result<Environment> {
     match stmt {
         Statement::If { cond, then, els } => {
-            let cond = evaluate(&*cond, env, func_env)?;
+            let cond = evaluate_with_limit(cond, env, func_env, limit)?;
             let cond_value = if let Expression::Int { value } = cond {
                 value
             } else {
-                anyhow::bail!("Expected to Expression::Int {:?}", cond);
+                anyhow::bail!(EvalError::TypeMismatch(cond));
             };
             if cond_value != 0 {
-                execute(&*then, env, func_env)
+                execute_with_limit(then, env, func_env, limit)
             } else {
-                execute(&*els, env, func_env)
+                execute_with_limit(els, env, func_env, limit)
             }
         }
+        Statement::Switch {
+            scrutinee,
+            cases,
+            default,
+        } => {
+            let value = evaluate_with_limit(scrutinee, env, func_env, limit)?;
+            let Expression::Int { value } = value else {
+                anyhow::bail!(EvalError::TypeMismatch(value));
+            };
+            let stmt = cases
+                .iter()
+                .find(|(case, _)| *case == value)
+                .map_or(default, |(_, stmt)| stmt);
+            execute_with_limit(stmt, env, func_env, limit)
+        }
         Statement::While { cond, stmt } => {
             let mut current_env = env.clone();
-            while let Expression::Int { value } = evaluate(&*cond, &current_env, func_env)? {
+            let mut iterations = 0;
+            while let Expression::Int { value } =
+                evaluate_with_limit(cond, &current_env, func_env, limit)?
+            {
                 if value == 0 {
                     break;
                 }
-                current_env = execute(&*stmt, &current_env, func_env)?;
+                count_iteration(&mut iterations, limit)?;
+                current_env = execute_with_limit(stmt, &current_env, func_env, limit)?;
+            }
+            Ok(current_env)
+        }
+        Statement::DoWhile { stmt, cond } => {
+            let mut iterations = 0;
+            count_iteration(&mut iterations, limit)?;
+            let mut current_env = execute_with_limit(stmt, env, func_env, limit)?;
+            while let Expression::Int { value } =
+                evaluate_with_limit(cond, &current_env, func_env, limit)?
+            {
+                if value == 0 {
+                    break;
+                }
+                count_iteration(&mut iterations, limit)?;
+                current_env = execute_with_limit(stmt, &current_env, func_env, limit)?;
             }
             Ok(current_env)
         }
         Statement::Assign { name, expr } => {
-            let value = evaluate(&*expr, &env, func_env)?;
+            let value = evaluate_with_limit(expr, env, func_env, limit)?;
             let mut current_env = env.clone();
             current_env.insert(name.to_string(), value);
             Ok(current_env)
@@ -142,10 +250,24 @@ pub fn execute(
         Statement::Sequence { stmts } => {
             let mut current_env = env.clone();
             for stmt in stmts {
-                current_env = execute(&*stmt, &current_env, func_env)?;
+                current_env = execute_with_limit(stmt, &current_env, func_env, limit)?;
             }
             Ok(current_env)
         }
+        Statement::Assert { expr } => {
+            if evaluate_with_limit(expr, env, func_env, limit)? == (Expression::Int { value: 0 }) {
+                anyhow::bail!(EvalError::AssertionFailed((**expr).clone()));
+            }
+            Ok(env.clone())
+        }
+        Statement::Let { name, expr, body } => {
+            let value = evaluate_with_limit(expr, env, func_env, limit)?;
+            let mut current_env = env.clone();
+            current_env.insert(name.to_string(), value);
+            let mut current_env = execute_with_limit(body, &current_env, func_env, limit)?;
+            restore_binding(&mut current_env, name.to_string(), env.get(name).cloned());
+            Ok(current_env)
+        }
         _ => anyhow::bail!("Unknown statement: {:?}", stmt),
     }
 }
@@ -171,12 +293,15 @@ mod tests {
 
     use crate::{
         ast::{Expression, Statement},
+        error::EvalError,
         func_exec_cbv::evaluate,
     };
 
     use std::collections::HashMap;
 
-    use super::define_function;
+    use crate::parser::{parse_expression, parse_program, parse_program_with_functions};
+
+    use super::{define_function, execute, execute_with_limit};
 
     #[test]
     fn test_func_exec_cbv1() -> Result<()> {
@@ -296,4 +421,232 @@ mod tests {
         assert_eq!(result, Expression::Int { value: 55 });
         Ok(())
     }
+
+    #[test]
+    fn test_division_by_zero() -> Result<()> {
+        let (stmt, func_env) =
+            parse_program_with_functions("def inv(n) { return := 100 / n }; x := inv(0)")?;
+        let err = execute(&stmt, &HashMap::new(), &func_env).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero");
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_variable() -> Result<()> {
+        let (stmt, func_env) =
+            parse_program_with_functions("def f(n) { return := n + m }; x := f(1)")?;
+        let err = execute(&stmt, &HashMap::new(), &func_env).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown variable: m");
+        Ok(())
+    }
+
+    #[test]
+    fn test_let() -> Result<()> {
+        let (body, func_env) =
+            parse_program_with_functions("def double(n) { return := n * 2 }; x := double(t)")?;
+        let stmt = Statement::Let {
+            name: "t".to_string(),
+            expr: Box::new(Expression::Int { value: 21 }),
+            body: Box::new(body),
+        };
+        let env = execute(&stmt, &HashMap::new(), &func_env)?;
+        assert_eq!(env.get("t"), None);
+        assert_eq!(env["x"], Expression::Int { value: 42 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_and_multi_arg_functions() -> Result<()> {
+        let (stmt, func_env) = parse_program_with_functions(
+            "
+            def answer() { return := 42 };
+            def sub(a, b) { return := a - b };
+            def nest() { return := sub(answer(), answer() / 2) };
+            x := answer();
+            y := sub(10, 3);
+            z := nest()
+            ",
+        )?;
+        let env = execute(&stmt, &HashMap::new(), &func_env)?;
+        assert_eq!(env["x"], Expression::Int { value: 42 });
+        assert_eq!(env["y"], Expression::Int { value: 7 });
+        assert_eq!(env["z"], Expression::Int { value: 21 });
+
+        let (stmt, func_env) =
+            parse_program_with_functions("def answer() { return := 42 }; x := answer(1)")?;
+        let err = execute(&stmt, &HashMap::new(), &func_env).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("answer expects 0 arguments but got 1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tail_call() -> Result<()> {
+        // 末尾呼び出しを再帰で実行するとテストスレッドのスタックが溢れる深さ
+        let (stmt, func_env) = parse_program_with_functions(
+            "
+            def count(n, acc) {
+                if n < 1 then return := acc else return := count(n - 1, acc + 2)
+            };
+            x := count(100000, 0)
+            ",
+        )?;
+        let env = execute(&stmt, &HashMap::new(), &func_env)?;
+        assert_eq!(env["x"], Expression::Int { value: 200000 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_do_while() -> Result<()> {
+        let (body, func_env) =
+            parse_program_with_functions("def inc(n) { return := n + 1 }; x := inc(x)")?;
+        let stmt = Statement::DoWhile {
+            stmt: Box::new(body),
+            cond: Box::new(parse_expression("x > 10")?),
+        };
+        let env = HashMap::from([("x".to_string(), Expression::Int { value: 0 })]);
+        let env = execute(&stmt, &env, &func_env)?;
+        assert_eq!(env["x"], Expression::Int { value: 1 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_pow() -> Result<()> {
+        // def pow2(n) { return := 2 ^ n }
+        let mut func_env = HashMap::new();
+        define_function(
+            "pow2",
+            vec!["n".to_string()],
+            Statement::Assign {
+                name: "return".to_string(),
+                expr: Box::new(Expression::BinExp {
+                    op: "^".to_string(),
+                    lhs: Box::new(Expression::Int { value: 2 }),
+                    rhs: Box::new(Expression::Var {
+                        name: "n".to_string(),
+                    }),
+                }),
+            },
+            &mut func_env,
+        );
+        let call = |value| Expression::Call {
+            name: "pow2".to_string(),
+            args: vec![Box::new(Expression::Int { value })],
+        };
+        let env = HashMap::new();
+        assert_eq!(
+            evaluate(&call(10), &env, &func_env)?,
+            Expression::Int { value: 1024 }
+        );
+        assert!(evaluate(&call(-1), &env, &func_env).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitwise() -> Result<()> {
+        // def f(a, b) { return := a OP b }
+        let call = |op: &str, a, b| -> Result<Expression> {
+            let mut func_env = HashMap::new();
+            define_function(
+                "f",
+                vec!["a".to_string(), "b".to_string()],
+                Statement::Assign {
+                    name: "return".to_string(),
+                    expr: Box::new(Expression::BinExp {
+                        op: op.to_string(),
+                        lhs: Box::new(Expression::Var {
+                            name: "a".to_string(),
+                        }),
+                        rhs: Box::new(Expression::Var {
+                            name: "b".to_string(),
+                        }),
+                    }),
+                },
+                &mut func_env,
+            );
+            let call = Expression::Call {
+                name: "f".to_string(),
+                args: vec![
+                    Box::new(Expression::Int { value: a }),
+                    Box::new(Expression::Int { value: b }),
+                ],
+            };
+            evaluate(&call, &HashMap::new(), &func_env)
+        };
+        assert_eq!(call("&", 12, 10)?, Expression::Int { value: 8 });
+        assert_eq!(call("|", 12, 10)?, Expression::Int { value: 14 });
+        assert_eq!(call("<<", 1, 10)?, Expression::Int { value: 1024 });
+        assert_eq!(call(">>", 1024, 3)?, Expression::Int { value: 128 });
+        assert!(call("<<", 1, 100).is_err());
+        assert!(call(">>", 1, -1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_kind() -> Result<()> {
+        let (stmt, func_env) =
+            parse_program_with_functions("def answer() { return := 42 }; x := answer(1)")?;
+        let err = execute(&stmt, &HashMap::new(), &func_env).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&EvalError::Arity {
+                name: "answer".to_string(),
+                expected: 0,
+                found: 1,
+            })
+        );
+        let err = execute(&parse_program("x := f(1)")?, &HashMap::new(), &func_env).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&EvalError::UnknownFunction("f".to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_limit() -> Result<()> {
+        let (stmt, func_env) = parse_program_with_functions(
+            "def spin(n) { while 1 do n := n + 1; return := n }; x := spin(0)",
+        )?;
+        let err = execute_with_limit(&stmt, &HashMap::new(), &func_env, Some(100)).unwrap_err();
+        assert_eq!(err.downcast_ref(), Some(&EvalError::LoopLimitExceeded));
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch_and_assert_in_function() -> Result<()> {
+        // def sign(n) { assert -1 < n; switch n { 0 => return := 10, 1 => return := 20, _ => return := 30 } }
+        let (_, mut func_env) = parse_program_with_functions("x := 0")?;
+        let body = Statement::Sequence {
+            stmts: vec![
+                Box::new(Statement::Assert {
+                    expr: Box::new(parse_expression("0 - 1 < n")?),
+                }),
+                Box::new(Statement::Switch {
+                    scrutinee: Box::new(parse_expression("n")?),
+                    cases: vec![
+                        (0, Box::new(parse_program("return := 10")?)),
+                        (1, Box::new(parse_program("return := 20")?)),
+                    ],
+                    default: Box::new(parse_program("return := 30")?),
+                }),
+            ],
+        };
+        define_function("sign", vec!["n".to_string()], body, &mut func_env);
+        let env = HashMap::new();
+        for (arg, expected) in [(0, 10), (1, 20), (5, 30)] {
+            let call = parse_expression(&format!("sign({})", arg))?;
+            assert_eq!(
+                evaluate(&call, &env, &func_env)?,
+                Expression::Int { value: expected }
+            );
+        }
+        let err = evaluate(&parse_expression("sign(0 - 2)")?, &env, &func_env).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(EvalError::AssertionFailed(_))
+        ));
+        Ok(())
+    }
 }
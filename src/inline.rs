@@ -0,0 +1,365 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use anyhow::Result;
+
+use crate::{
+    analysis::free_vars,
+    ast::{Expression, Statement},
+    error::EvalError,
+    func_exec_cbv::FunctionEnvironment,
+    visit::{walk_expr, walk_stmt, Fold},
+};
+
+// 関数の仮引数と本体で束縛される変数を、freshが返す新しい名前に一貫して付け替える。
+// returnは呼び出し側との受け渡しに使うので付け替えない。
+// FuncDef以外の文では、その中で代入・Letされる変数だけを付け替える
+pub fn alpha_rename(stmt: &Statement, fresh: &mut impl FnMut() -> String) -> Statement {
+    let mut bound = BTreeSet::new();
+    collect_bound(stmt, &mut bound);
+    bound.remove("return");
+    // 名前の順に割り当てて、同じ入力からは同じ結果を得る
+    let mut renamer = Renamer {
+        names: bound.into_iter().map(|name| (name, fresh())).collect(),
+    };
+    renamer.fold_stmt(stmt.clone())
+}
+
+fn collect_bound(stmt: &Statement, bound: &mut BTreeSet<String>) {
+    match stmt {
+        Statement::FuncDef { params, body } => {
+            bound.extend(params.iter().cloned());
+            collect_bound(body, bound);
+        }
+        Statement::Let { name, body, .. } => {
+            bound.insert(name.clone());
+            collect_bound(body, bound);
+        }
+        Statement::If { then, els, .. } => {
+            collect_bound(then, bound);
+            collect_bound(els, bound);
+        }
+        Statement::Switch { cases, default, .. } => {
+            for (_, stmt) in cases {
+                collect_bound(stmt, bound);
+            }
+            collect_bound(default, bound);
+        }
+        Statement::While { stmt, .. } | Statement::DoWhile { stmt, .. } => {
+            collect_bound(stmt, bound)
+        }
+        Statement::Sequence { stmts } => {
+            for stmt in stmts {
+                collect_bound(stmt, bound);
+            }
+        }
+        Statement::Assign { name, .. } => {
+            bound.insert(name.clone());
+        }
+        Statement::Assert { .. } => {}
+    }
+}
+
+struct Renamer {
+    names: HashMap<String, String>,
+}
+
+impl Renamer {
+    fn rename(&self, name: String) -> String {
+        self.names.get(&name).cloned().unwrap_or(name)
+    }
+}
+
+impl Fold for Renamer {
+    fn fold_expr(&mut self, expr: Expression) -> Expression {
+        match expr {
+            Expression::Var { name } => Expression::Var {
+                name: self.rename(name),
+            },
+            _ => walk_expr(self, expr),
+        }
+    }
+
+    fn fold_stmt(&mut self, stmt: Statement) -> Statement {
+        let stmt = match stmt {
+            Statement::Assign { name, expr } => Statement::Assign {
+                name: self.rename(name),
+                expr,
+            },
+            Statement::Let { name, expr, body } => Statement::Let {
+                name: self.rename(name),
+                expr,
+                body,
+            },
+            Statement::FuncDef { params, body } => Statement::FuncDef {
+                params: params.into_iter().map(|param| self.rename(param)).collect(),
+                body,
+            },
+            _ => stmt,
+        };
+        walk_stmt(self, stmt)
+    }
+}
+
+// 本体がreturnへの代入1つだけの関数の呼び出しを、引数を代入した本体の式で置き換える。
+// 再帰する関数と、仮引数以外の変数を読む関数はそのまま残す。
+// 引数は使われる箇所に複製されるので、値呼びと同じく各引数を1回ずつ評価するよう、
+// どの仮引数もちょうど1回使われる関数だけを展開する
+pub fn inline_calls(expr: Expression, func_env: &FunctionEnvironment) -> Result<Expression> {
+    let mut counter = 0;
+    inline_expr(expr, func_env, &mut counter)
+}
+
+fn inline_expr(
+    expr: Expression,
+    func_env: &FunctionEnvironment,
+    counter: &mut usize,
+) -> Result<Expression> {
+    match expr {
+        Expression::BinExp { op, lhs, rhs } => Ok(Expression::BinExp {
+            op,
+            lhs: Box::new(inline_expr(*lhs, func_env, counter)?),
+            rhs: Box::new(inline_expr(*rhs, func_env, counter)?),
+        }),
+        Expression::Call { name, args } => {
+            let args = args
+                .into_iter()
+                .map(|arg| inline_expr(*arg, func_env, counter))
+                .collect::<Result<Vec<_>>>()?;
+            let func = func_env
+                .get(&name)
+                .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+            if let Statement::FuncDef { params, .. } = func {
+                if params.len() != args.len() {
+                    anyhow::bail!(EvalError::Arity {
+                        name,
+                        expected: params.len(),
+                        found: args.len(),
+                    });
+                }
+            }
+            let inlinable =
+                single_return(func).is_some_and(|(params, body)| uses_each_once(params, body));
+            if is_recursive(&name, func_env) || !inlinable {
+                return Ok(Expression::Call {
+                    name,
+                    args: args.into_iter().map(Box::new).collect(),
+                });
+            }
+            let mut fresh = || {
+                *counter += 1;
+                format!("_inline{}", counter)
+            };
+            let renamed = alpha_rename(func, &mut fresh);
+            let (params, body) = single_return(&renamed).unwrap();
+            let mut substitution = Substitution {
+                values: params.iter().cloned().zip(args).collect(),
+            };
+            inline_expr(substitution.fold_expr(body.clone()), func_env, counter)
+        }
+        Expression::Int { .. } | Expression::Bool { .. } | Expression::Var { .. } => Ok(expr),
+    }
+}
+
+// 本体が { return := e } の形で、eが仮引数しか読まないなら仮引数とeを返す
+fn single_return(func: &Statement) -> Option<(&[String], &Expression)> {
+    let Statement::FuncDef { params, body } = func else {
+        return None;
+    };
+    let assign = match body.as_ref() {
+        Statement::Sequence { stmts } if stmts.len() == 1 => stmts[0].as_ref(),
+        stmt => stmt,
+    };
+    match assign {
+        Statement::Assign { name, expr }
+            if name == "return" && free_vars(expr).iter().all(|var| params.contains(var)) =>
+        {
+            Some((params, expr))
+        }
+        _ => None,
+    }
+}
+
+// bodyの中でparamsのそれぞれがちょうど1回ずつ読まれるか
+fn uses_each_once(params: &[String], body: &Expression) -> bool {
+    fn count(expr: &Expression, counts: &mut HashMap<String, usize>) {
+        match expr {
+            Expression::BinExp { lhs, rhs, .. } => {
+                count(lhs, counts);
+                count(rhs, counts);
+            }
+            Expression::Call { args, .. } => {
+                for arg in args {
+                    count(arg, counts);
+                }
+            }
+            Expression::Var { name } => *counts.entry(name.clone()).or_default() += 1,
+            Expression::Int { .. } | Expression::Bool { .. } => {}
+        }
+    }
+
+    let mut counts = HashMap::new();
+    count(body, &mut counts);
+    params.iter().all(|param| counts.get(param) == Some(&1))
+}
+
+// nameの本体から呼び出しをたどってname自身に戻ってこられるか
+fn is_recursive(name: &str, func_env: &FunctionEnvironment) -> bool {
+    let mut visited = HashSet::new();
+    let mut stack = vec![name.to_string()];
+    while let Some(current) = stack.pop() {
+        let Some(Statement::FuncDef { body, .. }) = func_env.get(&current) else {
+            continue;
+        };
+        for callee in called_functions(body) {
+            if callee == name {
+                return true;
+            }
+            if visited.insert(callee.clone()) {
+                stack.push(callee);
+            }
+        }
+    }
+    false
+}
+
+fn called_functions(stmt: &Statement) -> HashSet<String> {
+    struct Collector(HashSet<String>);
+
+    impl Fold for Collector {
+        fn fold_expr(&mut self, expr: Expression) -> Expression {
+            if let Expression::Call { name, .. } = &expr {
+                self.0.insert(name.clone());
+            }
+            walk_expr(self, expr)
+        }
+    }
+
+    let mut collector = Collector(HashSet::new());
+    collector.fold_stmt(stmt.clone());
+    collector.0
+}
+
+// 変数を式で置き換える。式には束縛がないので取り込みは起きない
+struct Substitution {
+    values: HashMap<String, Expression>,
+}
+
+impl Fold for Substitution {
+    fn fold_expr(&mut self, expr: Expression) -> Expression {
+        match expr {
+            Expression::Var { name } => match self.values.get(&name) {
+                Some(value) => value.clone(),
+                None => Expression::Var { name },
+            },
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+
+    use crate::{
+        analysis::{assigned_vars, used_vars},
+        ast::{Expression, Statement},
+        error::EvalError,
+        func_exec_cbv::evaluate,
+        parser::{parse_expression, parse_program_with_functions},
+    };
+
+    use super::{alpha_rename, inline_calls};
+
+    #[test]
+    fn test_alpha_rename() -> Result<()> {
+        let (_, func_env) = parse_program_with_functions(
+            "def sum(n) { i := n; while 0 < i do { return := return + i; i := i - 1 } }; x := 0",
+        )?;
+        let mut counter = 0;
+        let mut fresh = || {
+            counter += 1;
+            format!("_v{}", counter)
+        };
+        let renamed = alpha_rename(&func_env["sum"], &mut fresh);
+        let Statement::FuncDef { params, body } = &renamed else {
+            panic!("Expected Statement::FuncDef, got {:?}", renamed);
+        };
+        // 名前順にi, nが_v1, _v2になる
+        assert_eq!(params, &vec!["_v2".to_string()]);
+        let mut names = assigned_vars(body);
+        names.extend(used_vars(body));
+        assert!(names.contains("return"));
+        assert!(!names.contains("i") && !names.contains("n"));
+
+        let mut renamed_env = func_env.clone();
+        renamed_env.insert("sum".to_string(), renamed);
+        let call = parse_expression("sum(10)")?;
+        assert_eq!(
+            evaluate(&call, &HashMap::new(), &renamed_env)?,
+            evaluate(&call, &HashMap::new(), &func_env)?
+        );
+        assert_eq!(
+            evaluate(&call, &HashMap::new(), &renamed_env)?,
+            Expression::Int { value: 55 }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_calls() -> Result<()> {
+        let (_, func_env) = parse_program_with_functions(
+            "
+            def double(n) { return := n * 2 };
+            def quad(m) { return := double(double(m)) };
+            def fact(n) { if n < 1 then return := 1 else return := n * fact(n - 1) };
+            x := 0
+            ",
+        )?;
+        let expr = parse_expression("quad(x + 1) - fact(3)")?;
+        let inlined = inline_calls(expr.clone(), &func_env)?;
+        assert_eq!(inlined, parse_expression("(x + 1) * 2 * 2 - fact(3)")?);
+
+        let env = HashMap::from([("x".to_string(), Expression::Int { value: 4 })]);
+        assert_eq!(
+            evaluate(&inlined, &env, &func_env)?,
+            evaluate(&expr, &env, &func_env)?
+        );
+
+        let err = inline_calls(parse_expression("double(1, 2)")?, &func_env).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&EvalError::Arity {
+                name: "double".to_string(),
+                expected: 1,
+                found: 2,
+            })
+        );
+        let err = inline_calls(parse_expression("half(1)")?, &func_env).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&EvalError::UnknownFunction("half".to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_calls_evaluates_args_once() -> Result<()> {
+        let (_, func_env) = parse_program_with_functions(
+            "
+            def square(n) { return := n * n };
+            def first(a, b) { return := a };
+            x := 0
+            ",
+        )?;
+        // 使われない引数や2回使われる引数があると展開しない
+        for src in ["square(x + 1)", "first(1, 1 / 0)"] {
+            let expr = parse_expression(src)?;
+            assert_eq!(inline_calls(expr.clone(), &func_env)?, expr, "{}", src);
+        }
+        let expr = parse_expression("first(1, 1 / 0)")?;
+        assert!(evaluate(&inline_calls(expr, &func_env)?, &HashMap::new(), &func_env).is_err());
+        Ok(())
+    }
+}
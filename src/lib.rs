@@ -1,12 +1,23 @@
+pub mod analysis;
 pub mod ast;
+pub mod binop;
+pub mod cli;
+pub mod compile;
+pub mod error;
 pub mod eval_const;
 pub mod exec;
 pub mod fsa;
 pub mod func_exec_cbn;
 pub mod func_exec_cbv;
+pub mod inline;
+pub mod metrics;
+pub mod parser;
 pub mod regexp;
 pub mod rx_to_fsa;
 pub mod scanner;
 pub mod stack_machine;
+#[cfg(test)]
+pub mod testutil;
 pub mod tokens;
 pub mod tree_machine;
+pub mod visit;
@@ -0,0 +1,81 @@
+use std::{collections::HashMap, io, process};
+
+use anyhow::Result;
+use fake_world_wonderland::{
+    ast::IntVal,
+    cli::{format_environment, repl, run_file, Backend},
+    stack_machine::{execute, StackInstruction, StackOperation},
+};
+
+fn run_samples() -> Result<Vec<IntVal>> {
+    let first = execute(
+        vec![
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(2),
+            StackInstruction::Operation(StackOperation::Add),
+        ],
+        vec![1],
+    )?;
+    let second = execute(
+        vec![
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(5),
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(2),
+            StackInstruction::Operation(StackOperation::Sub),
+            StackInstruction::Operation(StackOperation::Mul),
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(4),
+            StackInstruction::Operation(StackOperation::Div),
+        ],
+        vec![2],
+    )?;
+    Ok(vec![first, second])
+}
+
+fn exit_with(err: anyhow::Error) -> ! {
+    eprintln!("error: {:#}", err);
+    process::exit(1);
+}
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--repl") {
+        return repl(io::stdin().lock(), &mut io::stdout(), &mut HashMap::new());
+    }
+    let mut backend = Backend::default();
+    let mut path = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--backend" {
+            let name = args.next().unwrap_or_default();
+            backend = name.parse().unwrap_or_else(|err| exit_with(err));
+        } else {
+            path = Some(arg);
+        }
+    }
+    if let Some(path) = path {
+        match run_file(path, backend) {
+            Ok(env) => print!("{}", format_environment(&env)),
+            Err(err) => exit_with(err),
+        }
+        return Ok(());
+    }
+    for result in run_samples()? {
+        println!("{}", result);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::run_samples;
+
+    #[test]
+    fn test_run_samples() -> Result<()> {
+        assert_eq!(run_samples()?, vec![3, 1]);
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use crate::ast::Expression;
+
+// 式の木の高さ。葉(IntとBoolとVar、引数のないCall)は1
+pub fn expr_depth(expr: &Expression) -> usize {
+    match expr {
+        Expression::BinExp { lhs, rhs, .. } => 1 + expr_depth(lhs).max(expr_depth(rhs)),
+        Expression::Call { args, .. } => {
+            1 + args.iter().map(|arg| expr_depth(arg)).max().unwrap_or(0)
+        }
+        Expression::Int { .. } | Expression::Bool { .. } | Expression::Var { .. } => 1,
+    }
+}
+
+// 式の木のノード数
+pub fn expr_size(expr: &Expression) -> usize {
+    match expr {
+        Expression::BinExp { lhs, rhs, .. } => 1 + expr_size(lhs) + expr_size(rhs),
+        Expression::Call { args, .. } => 1 + args.iter().map(|arg| expr_size(arg)).sum::<usize>(),
+        Expression::Int { .. } | Expression::Bool { .. } | Expression::Var { .. } => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{ast::Expression, parser::parse_expression};
+
+    use super::{expr_depth, expr_size};
+
+    #[test]
+    fn test_depth_and_size() -> Result<()> {
+        // (1 + 2) * (x - (3 / 4))
+        let expr = parse_expression("(1 + 2) * (x - (3 / 4))")?;
+        assert_eq!(expr_depth(&expr), 4);
+        assert_eq!(expr_size(&expr), 9);
+
+        let leaf = Expression::Int { value: 7 };
+        assert_eq!((expr_depth(&leaf), expr_size(&leaf)), (1, 1));
+
+        let call = parse_expression("f(a, g(b + 1), h())")?;
+        assert_eq!(expr_depth(&call), 4);
+        assert_eq!(expr_size(&call), 7);
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+use anyhow::{Context, Result};
+
+use std::collections::HashMap;
+
+use crate::{
+    ast::{Expression, Statement},
+    func_exec_cbv::{define_function, FunctionEnvironment},
+    scanner::{tokenize, TokenIterator},
+    tokens::{Keyword, Operator, Punct, Token},
+};
+
+pub struct Parser {
+    tokens: TokenIterator,
+}
+
+impl Parser {
+    pub fn new(input: &str) -> Self {
+        Self {
+            tokens: tokenize(input.to_string()),
+        }
+    }
+
+    fn peek(&mut self) -> Result<&Token> {
+        self.tokens.peek().context("unexpected end of input")
+    }
+
+    fn advance(&mut self) -> Result<Token> {
+        self.tokens.next().context("unexpected end of input")
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        let token = self.advance()?;
+        if token != expected {
+            anyhow::bail!("Expected {:?} but {:?}", expected, token);
+        }
+        Ok(())
+    }
+
+    // (def | stmt) (; (def | stmt))* End
+    pub fn parse_program(&mut self) -> Result<(Statement, FunctionEnvironment)> {
+        let mut stmts = vec![];
+        let mut func_env = HashMap::new();
+        loop {
+            if self.peek()? == &Token::Keyword(Keyword::Def) {
+                self.parse_function(&mut func_env)?;
+            } else {
+                stmts.push(Box::new(self.parse_statement()?));
+            }
+            if self.peek()? != &Token::Punct(Punct::Semicolon) {
+                break;
+            }
+            self.advance()?;
+        }
+        self.expect(Token::End)?;
+        if stmts.len() == 1 {
+            Ok((*stmts.remove(0), func_env))
+        } else {
+            Ok((Statement::Sequence { stmts }, func_env))
+        }
+    }
+
+    // def name(param, ...) { ... }
+    fn parse_function(&mut self, func_env: &mut FunctionEnvironment) -> Result<()> {
+        self.expect(Token::Keyword(Keyword::Def))?;
+        let name = match self.advance()? {
+            Token::Identifier(name) => name,
+            token => anyhow::bail!("Expected function name but {:?}", token),
+        };
+        self.expect(Token::Punct(Punct::LParen))?;
+        let mut params = vec![];
+        if self.peek()? != &Token::Punct(Punct::RParen) {
+            loop {
+                match self.advance()? {
+                    Token::Identifier(param) => params.push(param),
+                    token => anyhow::bail!("Expected parameter name but {:?}", token),
+                }
+                if self.peek()? != &Token::Punct(Punct::Comma) {
+                    break;
+                }
+                self.advance()?;
+            }
+        }
+        self.expect(Token::Punct(Punct::RParen))?;
+        if self.peek()? != &Token::Punct(Punct::LBrace) {
+            anyhow::bail!("Expected function body but {:?}", self.peek()?);
+        }
+        let body = self.parse_statement()?;
+        define_function(&name, params, body, func_env);
+        Ok(())
+    }
+
+    pub fn parse_statement(&mut self) -> Result<Statement> {
+        match self.advance()? {
+            Token::Punct(Punct::LBrace) => {
+                let mut stmts = vec![];
+                if self.peek()? == &Token::Punct(Punct::RBrace) {
+                    self.advance()?;
+                    return Ok(Statement::Sequence { stmts });
+                }
+                stmts.push(Box::new(self.parse_statement()?));
+                while self.peek()? == &Token::Punct(Punct::Semicolon) {
+                    self.advance()?;
+                    stmts.push(Box::new(self.parse_statement()?));
+                }
+                self.expect(Token::Punct(Punct::RBrace))?;
+                Ok(Statement::Sequence { stmts })
+            }
+            Token::Keyword(Keyword::While) => {
+                let cond = self.parse_expression()?;
+                self.expect(Token::Keyword(Keyword::Do))?;
+                let stmt = self.parse_statement()?;
+                Ok(Statement::While {
+                    cond: Box::new(cond),
+                    stmt: Box::new(stmt),
+                })
+            }
+            Token::Keyword(Keyword::If) => {
+                let cond = self.parse_expression()?;
+                self.expect(Token::Keyword(Keyword::Then))?;
+                let then = self.parse_statement()?;
+                self.expect(Token::Keyword(Keyword::Else))?;
+                let els = self.parse_statement()?;
+                Ok(Statement::If {
+                    cond: Box::new(cond),
+                    then: Box::new(then),
+                    els: Box::new(els),
+                })
+            }
+            Token::Identifier(name) => {
+                self.expect(Token::Op(Operator::Assign))?;
+                let expr = self.parse_expression()?;
+                Ok(Statement::Assign {
+                    name,
+                    expr: Box::new(expr),
+                })
+            }
+            token => anyhow::bail!("Unexpected token: {:?}", token),
+        }
+    }
+
+    // additive ((< | >) additive)*
+    pub fn parse_expression(&mut self) -> Result<Expression> {
+        let mut lhs = self.parse_additive()?;
+        loop {
+            let op = match self.peek()? {
+                Token::Op(Operator::Lt) => "<",
+                Token::Op(Operator::Gt) => ">",
+                _ => break,
+            };
+            self.advance()?;
+            let rhs = self.parse_additive()?;
+            lhs = binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    // term ((+ | -) term)*
+    fn parse_additive(&mut self) -> Result<Expression> {
+        let mut lhs = self.parse_term()?;
+        loop {
+            let op = match self.peek()? {
+                Token::Op(Operator::Plus) => "+",
+                Token::Op(Operator::Minus) => "-",
+                _ => break,
+            };
+            self.advance()?;
+            let rhs = self.parse_term()?;
+            lhs = binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    // primary ((* | /) primary)*
+    fn parse_term(&mut self) -> Result<Expression> {
+        let mut lhs = self.parse_primary()?;
+        loop {
+            let op = match self.peek()? {
+                Token::Op(Operator::Star) => "*",
+                Token::Op(Operator::Slash) => "/",
+                _ => break,
+            };
+            self.advance()?;
+            let rhs = self.parse_primary()?;
+            lhs = binary(op, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression> {
+        match self.advance()? {
+            Token::Number(value, _) => Ok(Expression::Int { value }),
+            Token::Keyword(Keyword::True) => Ok(Expression::Bool { value: true }),
+            Token::Keyword(Keyword::False) => Ok(Expression::Bool { value: false }),
+            Token::Identifier(name) => {
+                if self.peek()? != &Token::Punct(Punct::LParen) {
+                    return Ok(Expression::Var { name });
+                }
+                self.advance()?;
+                let mut args = vec![];
+                if self.peek()? != &Token::Punct(Punct::RParen) {
+                    loop {
+                        args.push(Box::new(self.parse_expression()?));
+                        if self.peek()? != &Token::Punct(Punct::Comma) {
+                            break;
+                        }
+                        self.advance()?;
+                    }
+                }
+                self.expect(Token::Punct(Punct::RParen))?;
+                Ok(Expression::Call { name, args })
+            }
+            Token::Punct(Punct::LParen) => {
+                let expr = self.parse_expression()?;
+                self.expect(Token::Punct(Punct::RParen))?;
+                Ok(expr)
+            }
+            token => anyhow::bail!("Unexpected token: {:?}", token),
+        }
+    }
+}
+
+fn binary(op: &str, lhs: Expression, rhs: Expression) -> Expression {
+    Expression::BinExp {
+        op: op.to_string(),
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    }
+}
+
+pub fn parse_program(input: &str) -> Result<Statement> {
+    let (stmt, func_env) = Parser::new(input).parse_program()?;
+    if !func_env.is_empty() {
+        anyhow::bail!("Function definitions require parse_program_with_functions");
+    }
+    Ok(stmt)
+}
+
+// 入力全体を1つの式として解析する
+pub fn parse_expression(input: &str) -> Result<Expression> {
+    let mut parser = Parser::new(input);
+    let expr = parser.parse_expression()?;
+    parser.expect(Token::End)?;
+    Ok(expr)
+}
+
+pub fn parse_program_with_functions(input: &str) -> Result<(Statement, FunctionEnvironment)> {
+    Parser::new(input).parse_program()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use std::collections::HashMap;
+
+    use crate::{
+        ast::{Expression, Statement},
+        eval_const::eval_const,
+        exec::execute,
+        func_exec_cbv,
+    };
+
+    use super::{parse_expression, parse_program, parse_program_with_functions, Parser};
+
+    fn eval_expr(input: &str) -> Result<Expression> {
+        eval_const(Parser::new(input).parse_expression()?)
+    }
+
+    #[test]
+    fn test_parse_assign() -> Result<()> {
+        let stmt = parse_program("i := i - 1")?;
+        assert_eq!(
+            stmt,
+            Statement::Assign {
+                name: String::from("i"),
+                expr: Box::new(Expression::BinExp {
+                    op: String::from("-"),
+                    lhs: Box::new(Expression::Var {
+                        name: String::from("i"),
+                    }),
+                    rhs: Box::new(Expression::Int { value: 1 }),
+                }),
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_and_execute() -> Result<()> {
+        let stmt = parse_program("i := 10; while 0 < i do i := i - 1")?;
+
+        let mut expect_env = HashMap::new();
+        expect_env.insert(String::from("i"), Expression::Int { value: 0 });
+
+        let res_env = execute(stmt, HashMap::new())?;
+        assert_eq!(expect_env, res_env);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_block() -> Result<()> {
+        let sample = "
+        {
+            i := 10;        // this is comment.
+            sum := 0;
+            while 0 < i do {
+              if i > 5 then sum := sum + i else sum := sum;
+              i := i - 1
+            }
+        }
+        ";
+        let stmt = parse_program(sample)?;
+
+        let mut expect_env = HashMap::new();
+        expect_env.insert(String::from("i"), Expression::Int { value: 0 });
+        expect_env.insert(String::from("sum"), Expression::Int { value: 40 });
+
+        let res_env = execute(stmt, HashMap::new())?;
+        assert_eq!(expect_env, res_env);
+        Ok(())
+    }
+
+    #[test]
+    fn test_precedence() -> Result<()> {
+        assert_eq!(eval_expr("2 + 3 * 4")?, Expression::Int { value: 14 });
+        assert_eq!(eval_expr("(2 + 3) * 4")?, Expression::Int { value: 20 });
+        assert_eq!(eval_expr("10 - 4 - 3")?, Expression::Int { value: 3 });
+        assert_eq!(eval_expr("8 / 2 * 3")?, Expression::Int { value: 12 });
+        assert_eq!(eval_expr("1 + 2 < 2 * 3")?, Expression::Int { value: 1 });
+        assert_eq!(
+            eval_expr("(1 + 2) * 2 > 2 * (3 + 1)")?,
+            Expression::Int { value: 0 }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_function() -> Result<()> {
+        let sample = "
+        def fact(n) {
+            if n < 1 then return := 1 else return := n * fact(n - 1)
+        };
+        def ten() {
+            return := 10
+        };
+        result := fact(5) + ten()
+        ";
+        let (stmt, func_env) = parse_program_with_functions(sample)?;
+        assert_eq!(func_env.len(), 2);
+
+        let res_env = func_exec_cbv::execute(&stmt, &HashMap::new(), &func_env)?;
+        assert_eq!(res_env["result"], Expression::Int { value: 130 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_call() -> Result<()> {
+        let expr = Parser::new("add(1, f())").parse_expression()?;
+        assert_eq!(
+            expr,
+            Expression::Call {
+                name: String::from("add"),
+                args: vec![
+                    Box::new(Expression::Int { value: 1 }),
+                    Box::new(Expression::Call {
+                        name: String::from("f"),
+                        args: vec![],
+                    }),
+                ],
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error() {
+        assert!(parse_program("i := ").is_err());
+        assert!(parse_program("while i i := 1").is_err());
+        assert!(parse_program("{ i := 1").is_err());
+        assert!(parse_program("i := (1 + 2").is_err());
+        assert!(parse_program("def f() { return := 1 }").is_err());
+        assert!(parse_program_with_functions("def f(1) { return := 1 }").is_err());
+        assert!(parse_expression("x := 1").is_err());
+        assert!(parse_expression("1 + x").is_ok());
+    }
+
+    #[test]
+    fn test_parse_bool() -> Result<()> {
+        let stmt = parse_program("if true then x := 1 else x := 2")?;
+        let Statement::If { cond, .. } = &stmt else {
+            panic!("Expected Statement::If, got {:?}", stmt);
+        };
+        assert_eq!(**cond, Expression::Bool { value: true });
+        let env = execute(stmt, HashMap::new())?;
+        assert_eq!(env["x"], Expression::Int { value: 1 });
+
+        let env = execute(
+            parse_program("if false then x := 1 else x := true + true")?,
+            HashMap::new(),
+        )?;
+        assert_eq!(env["x"], Expression::Int { value: 2 });
+        assert_eq!(eval_expr("false < true")?, Expression::Int { value: 1 });
+        Ok(())
+    }
+}
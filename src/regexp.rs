@@ -1,10 +1,121 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use anyhow::{Context, Result};
+
+use crate::{
+    fsa::{State, DFA},
+    rx_to_fsa::NFAConstructor,
+};
+
+// DFAに変換済みの正規表現。パターンに現れない文字はAnyや否定クラスから見るとどれも同じなので、
+// まとめてotherという1つの記号に置き換えてから遷移する
+#[derive(Debug, Clone)]
+pub struct CompiledRegExp {
+    dfa: DFA,
+    alphabet: HashSet<char>,
+    other: char,
+}
+
+impl CompiledRegExp {
+    // 入力全体が一致するか。is_full_matchと同じ結果になる
+    pub fn is_match(&self, input: &str) -> bool {
+        let mut current = self.dfa.start;
+        for ch in input.chars() {
+            match self.step(current, ch) {
+                Some(next) => current = next,
+                None => return false,
+            }
+        }
+        self.dfa.finals.contains(&current)
+    }
+
+    // 最長一致: 進めるだけ進み、最後に受理状態だった位置のバイト長を返す。空の一致はNone
+    pub fn longest_prefix(&self, input: &str) -> Option<usize> {
+        let mut current = self.dfa.start;
+        let mut longest = None;
+        for (offset, ch) in input.char_indices() {
+            match self.step(current, ch) {
+                Some(next) => current = next,
+                None => break,
+            }
+            if self.dfa.finals.contains(&current) {
+                longest = Some(offset + ch.len_utf8());
+            }
+        }
+        longest
+    }
+
+    // パターンに現れない文字はotherとして遷移するので、DFAのアルファベットにはotherも含まれる
+    pub fn dfa(&self) -> &DFA {
+        &self.dfa
+    }
+
+    fn step(&self, current: State, ch: char) -> Option<State> {
+        let sym = if self.alphabet.contains(&ch) {
+            ch
+        } else {
+            self.other
+        };
+        self.dfa.transition.get(&current)?.get(&sym).copied()
+    }
+}
+
+// alphabetに含まれない最小の文字。パターンに現れない文字すべての代表にする
+fn outside_symbol(alphabet: &HashSet<char>) -> Result<char> {
+    ('\0'..=char::MAX)
+        .find(|ch| !alphabet.contains(ch))
+        .context("no character is left for symbols outside the pattern")
+}
+
+type Memo = HashMap<(*const RegExp, usize), Option<HashSet<usize>>>;
+
+// 一回の照合で共有する入力と設定、メモ。位置はすべて文字単位。
+// limitを設定するとmatch_memoの呼び出し回数がそれを超えた時点で照合を打ち切る
+struct Matcher {
+    input: Vec<char>,
+    case_insensitive: bool,
+    memo: Memo,
+    steps: usize,
+    limit: Option<usize>,
+}
+
+impl Matcher {
+    fn new(input: &str, case_insensitive: bool) -> Matcher {
+        Matcher {
+            input: input.chars().collect(),
+            case_insensitive,
+            memo: HashMap::new(),
+            steps: 0,
+            limit: None,
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        self.limit.is_some_and(|limit| self.steps > limit)
+    }
+
+    // 大文字小文字を区別しない場合は、chの大文字・小文字も候補にする
+    fn variants(&self, ch: char) -> Vec<char> {
+        let mut variants = vec![ch];
+        if self.case_insensitive {
+            variants.extend(ch.to_lowercase());
+            variants.extend(ch.to_uppercase());
+        }
+        variants
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RegExp {
     Char(char),
     Any,
+    // 空文字列だけにマッチする
     Empty,
+    // 何にもマッチしない(空集合)。Orの単位元、Seqの零元になる
+    Never,
     Seq {
         left: Box<RegExp>,
         right: Box<RegExp>,
@@ -14,68 +125,245 @@ pub enum RegExp {
         right: Box<RegExp>,
     },
     Repeat(Box<RegExp>),
+    Plus(Box<RegExp>),
+    Optional(Box<RegExp>),
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    RepeatN {
+        inner: Box<RegExp>,
+        min: usize,
+        max: Option<usize>,
+    },
+    Start,
+    End,
 }
 
 impl RegExp {
-    fn repeat_match(&self, input: &str, pos: usize, acc: HashSet<usize>) -> Option<HashSet<usize>> {
-        let mut next = HashSet::new();
-        let res = self._match(input, pos);
-        if res.is_none() {
-            return Some(acc);
-        }
-        for q in res? {
-            if !acc.contains(&q) {
-                next.insert(q);
+    // partsを右結合のSeqにまとめる。空ならEmpty、1つならそれ自身
+    pub fn seq(parts: Vec<RegExp>) -> RegExp {
+        parts
+            .into_iter()
+            .rev()
+            .reduce(|right, left| RegExp::Seq {
+                left: Box::new(left),
+                right: Box::new(right),
+            })
+            .unwrap_or(RegExp::Empty)
+    }
+
+    // partsを右結合のOrにまとめる。空ならNever、1つならそれ自身
+    pub fn alt(parts: Vec<RegExp>) -> RegExp {
+        parts
+            .into_iter()
+            .rev()
+            .reduce(|right, left| RegExp::Or {
+                left: Box::new(left),
+                right: Box::new(right),
+            })
+            .unwrap_or(RegExp::Never)
+    }
+
+    // 文字列sそのものに一致するCharのSeq。空文字列ならEmpty
+    pub fn literal(s: &str) -> RegExp {
+        RegExp::seq(s.chars().map(RegExp::Char).collect())
+    }
+
+    // chがいずれかの範囲に含まれるか。negatedなら結果を反転する
+    pub fn class_contains(ranges: &[(char, char)], negated: bool, ch: char) -> bool {
+        ranges.iter().any(|(low, high)| *low <= ch && ch <= *high) != negated
+    }
+
+    // posから0回以上繰り返して到達できる位置の集合。新しく得た位置だけを調べる
+    fn repeat_match(&self, pos: usize, m: &mut Matcher) -> HashSet<usize> {
+        let mut result = HashSet::from([pos]);
+        let mut worklist = vec![pos];
+        while let Some(p) = worklist.pop() {
+            for q in self.match_memo(p, m).into_iter().flatten() {
+                if result.insert(q) {
+                    worklist.push(q);
+                }
             }
         }
+        result
+    }
 
-        if next.is_empty() {
-            Some(acc)
-        } else {
-            match (acc, next) {
-                (acc, next) => {
-                    let mut new_acc = HashSet::new();
-                    for q in acc {
-                        new_acc.insert(q);
-                    }
-                    for q in next {
-                        new_acc.insert(q);
-                    }
-                    self.repeat_match(input, pos + 1, new_acc)
+    pub fn _match(&self, input: &str, pos: usize) -> Option<HashSet<usize>> {
+        self.match_memo(pos, &mut Matcher::new(input, false))
+    }
+
+    // 部分式の照合を呼んだ回数をstepsに足しながら_matchを行い、limitを超えたらエラーにする。
+    // stepsは呼び出しをまたいで累積するので、複数の照合で予算を共有できる
+    pub fn _match_budget(
+        &self,
+        input: &str,
+        pos: usize,
+        steps: &mut usize,
+        limit: usize,
+    ) -> Result<Option<HashSet<usize>>> {
+        let mut m = Matcher::new(input, false);
+        m.steps = *steps;
+        m.limit = Some(limit);
+        let result = self.match_memo(pos, &mut m);
+        *steps = m.steps;
+        if m.exhausted() {
+            anyhow::bail!("match budget of {} steps exceeded", limit);
+        }
+        Ok(result)
+    }
+
+    // CharとClassの比較で大文字と小文字を区別しない_match。
+    // Seq/Or/Repeatなどは部分式の結果を組み合わせるだけなので、そのまま大文字小文字を無視した一致になる
+    pub fn match_ci(&self, input: &str, pos: usize) -> Option<HashSet<usize>> {
+        self.match_memo(pos, &mut Matcher::new(input, true))
+    }
+
+    // 先頭から一致させたとき、入力の末尾まで一致するか
+    pub fn is_full_match(&self, input: &str) -> bool {
+        self._match(input, 0)
+            .is_some_and(|positions| positions.contains(&input.chars().count()))
+    }
+
+    // posから始まる一致のうち最も長いものの終了位置。字句解析の最長一致に使う
+    pub fn longest_match(&self, input: &str, pos: usize) -> Option<usize> {
+        self._match(input, pos)?.into_iter().max()
+    }
+
+    // rx_to_fsaでDFAに変換する。RepeatNやStart/Endを含むパターンはエラー
+    pub fn compile(&self) -> Result<CompiledRegExp> {
+        let alphabet = self.alphabet();
+        let other = outside_symbol(&alphabet)?;
+        let mut symbols = alphabet.clone();
+        symbols.insert(other);
+        Ok(CompiledRegExp {
+            dfa: self.to_dfa_over(&symbols)?,
+            alphabet,
+            other,
+        })
+    }
+
+    // 両者が同じ文字列の集合に一致するか。両方のパターンに現れない文字は1つの記号で代表させて、
+    // 最小化したDFA同士を比べる。rx_to_fsaが扱えないパターンはエラー
+    pub fn equivalent(&self, other: &RegExp) -> Result<bool> {
+        let mut symbols: HashSet<char> =
+            self.alphabet().union(&other.alphabet()).cloned().collect();
+        symbols.insert(outside_symbol(&symbols)?);
+        Ok(self
+            .to_dfa_over(&symbols)?
+            .equivalent(&other.to_dfa_over(&symbols)?))
+    }
+
+    fn to_dfa_over(&self, symbols: &HashSet<char>) -> Result<DFA> {
+        Ok(NFAConstructor::new()
+            .rx_to_nfa(self, symbols)?
+            .to_dfa()
+            .minimize())
+    }
+
+    // パターンに現れる文字の集合。Classは範囲内の文字をすべて含め、AnyやStart/Endは何も加えない
+    pub fn alphabet(&self) -> HashSet<char> {
+        let mut result = HashSet::new();
+        self.collect_alphabet(&mut result);
+        result
+    }
+
+    fn collect_alphabet(&self, result: &mut HashSet<char>) {
+        match self {
+            RegExp::Char(ch) => {
+                result.insert(*ch);
+            }
+            RegExp::Class { ranges, .. } => {
+                for (low, high) in ranges {
+                    result.extend(*low..=*high);
                 }
             }
+            RegExp::Seq { left, right } | RegExp::Or { left, right } => {
+                left.collect_alphabet(result);
+                right.collect_alphabet(result);
+            }
+            RegExp::Repeat(inner) | RegExp::Plus(inner) | RegExp::Optional(inner) => {
+                inner.collect_alphabet(result)
+            }
+            RegExp::RepeatN { inner, .. } => inner.collect_alphabet(result),
+            RegExp::Any | RegExp::Empty | RegExp::Never | RegExp::Start | RegExp::End => {}
         }
     }
 
-    pub fn _match(&self, input: &str, pos: usize) -> Option<HashSet<usize>> {
+    // (部分式, 位置) ごとに結果を記録し、同じ組を二度計算しない
+    fn match_memo(&self, pos: usize, m: &mut Matcher) -> Option<HashSet<usize>> {
+        // 予算を使い切ったら以降はすべて不一致として即座に戻る
+        m.steps += 1;
+        if m.exhausted() {
+            return None;
+        }
+        let key = (self as *const RegExp, pos);
+        if let Some(result) = m.memo.get(&key) {
+            return result.clone();
+        }
+        let result = self.match_step(pos, m);
+        m.memo.insert(key, result.clone());
+        result
+    }
+
+    fn match_step(&self, pos: usize, m: &mut Matcher) -> Option<HashSet<usize>> {
         match self {
             RegExp::Char(c) => {
-                if pos < input.len() && input.chars().nth(pos)? == *c {
-                    return Some(HashSet::from([pos + 1]));
+                if pos < m.input.len() {
+                    let ch = m.input[pos];
+                    if m.variants(ch).contains(c) {
+                        return Some(HashSet::from([pos + 1]));
+                    }
+                }
+            }
+            RegExp::Class { ranges, negated } => {
+                if pos < m.input.len() {
+                    let ch = m.input[pos];
+                    let in_ranges = m
+                        .variants(ch)
+                        .into_iter()
+                        .any(|variant| RegExp::class_contains(ranges, false, variant));
+                    if in_ranges != *negated {
+                        return Some(HashSet::from([pos + 1]));
+                    }
                 }
             }
             RegExp::Any => {
-                if pos < input.len() {
+                if pos < m.input.len() {
                     return Some(HashSet::from([pos + 1]));
                 }
             }
+            RegExp::Start => {
+                if pos == 0 {
+                    return Some(HashSet::from([pos]));
+                }
+            }
+            RegExp::End => {
+                if pos == m.input.len() {
+                    return Some(HashSet::from([pos]));
+                }
+            }
+            RegExp::Never => {}
             RegExp::Empty => {
-                if pos <= input.len() {
+                if pos <= m.input.len() {
                     return Some(HashSet::from([pos]));
                 }
             }
             RegExp::Seq { left, right } => {
                 let mut result = HashSet::new();
-                for pos_left in left._match(input, pos)? {
-                    for pos_right in right._match(input, pos_left)? {
-                        result.insert(pos_right);
+                for pos_left in left.match_memo(pos, m)? {
+                    // 左の一致位置のうち、右が一致しないものは読み飛ばす
+                    if let Some(pos_rights) = right.match_memo(pos_left, m) {
+                        result.extend(pos_rights);
                     }
                 }
-                return Some(result);
+                if !result.is_empty() {
+                    return Some(result);
+                }
             }
             RegExp::Or { left, right } => {
-                let left_result = left._match(input, pos);
-                let right_result = right._match(input, pos);
+                let left_result = left.match_memo(pos, m);
+                let right_result = right.match_memo(pos, m);
                 match (left_result, right_result) {
                     (Some(left_result), Some(right_result)) => {
                         let mut result = HashSet::new();
@@ -107,16 +395,361 @@ impl RegExp {
                 }
             }
             RegExp::Repeat(reg) => {
-                let initial_pos = HashSet::from([pos]);
-                return reg.repeat_match(input, pos, initial_pos);
+                return Some(reg.repeat_match(pos, m));
+            }
+            RegExp::RepeatN { inner, min, max } => {
+                // k回繰り返した後の位置集合を1回ずつ広げていく
+                let mut current = HashSet::from([pos]);
+                let mut result = HashSet::new();
+                if *min == 0 {
+                    result.insert(pos);
+                }
+                let mut count = 0;
+                while max.is_none_or(|max| count < max) {
+                    count += 1;
+                    let mut next = HashSet::new();
+                    for p in &current {
+                        if let Some(positions) = inner.match_memo(*p, m) {
+                            next.extend(positions);
+                        }
+                    }
+                    if next.is_empty() {
+                        break;
+                    }
+                    if count >= *min {
+                        // 既に得た位置しか出てこなければ、以降も新しい位置は増えない
+                        if count > *min && next.is_subset(&result) {
+                            break;
+                        }
+                        result.extend(next.iter().cloned());
+                    }
+                    current = next;
+                }
+                if !result.is_empty() {
+                    return Some(result);
+                }
+            }
+            RegExp::Optional(reg) => {
+                let mut result = HashSet::from([pos]);
+                if let Some(positions) = reg.match_memo(pos, m) {
+                    result.extend(positions);
+                }
+                return Some(result);
+            }
+            RegExp::Plus(reg) => {
+                // Seq { reg, Repeat(reg) } と同じ
+                let mut result = HashSet::new();
+                for pos_first in reg.match_memo(pos, m)? {
+                    result.extend(reg.repeat_match(pos_first, m));
+                }
+                if !result.is_empty() {
+                    return Some(result);
+                }
             }
         }
         None
     }
 }
 
+// \d \w \s とその否定 \D \W \S を文字クラスの範囲に展開する
+fn shorthand_class(ch: char) -> Option<(Vec<(char, char)>, bool)> {
+    let ranges = match ch.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('A', 'Z'), ('a', 'z'), ('0', '9'), ('_', '_')],
+        's' => vec![(' ', ' '), ('\t', '\r')],
+        _ => return None,
+    };
+    Some((ranges, ch.is_ascii_uppercase()))
+}
+
+// 正規表現の文字列表現を読むための再帰下降パーサ
+struct PatternParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl PatternParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn advance(&mut self) -> Result<char> {
+        let ch = self.peek().context("unexpected end of pattern")?;
+        self.pos += 1;
+        Ok(ch)
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        if self.peek() == Some(ch) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    // seq (| seq)*
+    fn parse_alt(&mut self) -> Result<RegExp> {
+        let mut left = self.parse_seq()?;
+        while self.eat('|') {
+            let right = self.parse_seq()?;
+            left = RegExp::Or {
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    // postfix*
+    fn parse_seq(&mut self) -> Result<RegExp> {
+        let mut parts = vec![];
+        while let Some(ch) = self.peek() {
+            if ch == '|' || ch == ')' {
+                break;
+            }
+            parts.push(self.parse_postfix()?);
+        }
+        let mut result = match parts.pop() {
+            Some(last) => last,
+            None => return Ok(RegExp::Empty),
+        };
+        while let Some(part) = parts.pop() {
+            result = RegExp::Seq {
+                left: Box::new(part),
+                right: Box::new(result),
+            };
+        }
+        Ok(result)
+    }
+
+    // atom (* | + | ? | {n,m})*
+    fn parse_postfix(&mut self) -> Result<RegExp> {
+        let mut reg = self.parse_atom()?;
+        loop {
+            reg = match self.peek() {
+                Some('*') => RegExp::Repeat(Box::new(reg)),
+                Some('+') => RegExp::Plus(Box::new(reg)),
+                Some('?') => RegExp::Optional(Box::new(reg)),
+                Some('{') => {
+                    self.advance()?;
+                    let min = self.parse_number()?;
+                    let max = if self.eat(',') {
+                        if self.peek() == Some('}') {
+                            None
+                        } else {
+                            Some(self.parse_number()?)
+                        }
+                    } else {
+                        Some(min)
+                    };
+                    if !self.eat('}') {
+                        anyhow::bail!("Expected '}}' at {}", self.pos);
+                    }
+                    reg = RegExp::RepeatN {
+                        inner: Box::new(reg),
+                        min,
+                        max,
+                    };
+                    continue;
+                }
+                _ => return Ok(reg),
+            };
+            self.advance()?;
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<usize> {
+        let start = self.pos;
+        while self.peek().is_some_and(|ch| ch.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits
+            .parse()
+            .with_context(|| format!("Expected a number at {}", start))
+    }
+
+    fn parse_atom(&mut self) -> Result<RegExp> {
+        match self.advance()? {
+            '(' => {
+                let reg = self.parse_alt()?;
+                if !self.eat(')') {
+                    anyhow::bail!("Expected ')' at {}", self.pos);
+                }
+                Ok(reg)
+            }
+            '.' => Ok(RegExp::Any),
+            '^' => Ok(RegExp::Start),
+            '$' => Ok(RegExp::End),
+            '[' => self.parse_class(),
+            '\\' => {
+                let ch = self.advance()?;
+                match shorthand_class(ch) {
+                    Some((ranges, negated)) => Ok(RegExp::Class { ranges, negated }),
+                    None => Ok(RegExp::Char(ch)),
+                }
+            }
+            ch @ ('*' | '+' | '?' | '{' | '}' | ']') => {
+                anyhow::bail!("Unexpected '{}' at {}", ch, self.pos - 1)
+            }
+            ch => Ok(RegExp::Char(ch)),
+        }
+    }
+
+    // [^a-z0-9_] の '[' より後ろ
+    fn parse_class(&mut self) -> Result<RegExp> {
+        let negated = self.eat('^');
+        let mut ranges = vec![];
+        while !self.eat(']') {
+            if self.peek() == Some('\\') {
+                if let Some((shorthand, negated_shorthand)) = self
+                    .chars
+                    .get(self.pos + 1)
+                    .and_then(|ch| shorthand_class(*ch))
+                {
+                    if negated_shorthand {
+                        anyhow::bail!(
+                            "Negated shorthand is not supported in a class at {}",
+                            self.pos
+                        );
+                    }
+                    self.pos += 2;
+                    ranges.extend(shorthand);
+                    continue;
+                }
+            }
+            let low = self.parse_class_char()?;
+            let high = if self.peek() == Some('-') && self.chars.get(self.pos + 1) != Some(&']') {
+                self.advance()?;
+                self.parse_class_char()?
+            } else {
+                low
+            };
+            ranges.push((low, high));
+        }
+        Ok(RegExp::Class { ranges, negated })
+    }
+
+    fn parse_class_char(&mut self) -> Result<char> {
+        match self.advance()? {
+            '\\' => self.advance(),
+            ch => Ok(ch),
+        }
+    }
+}
+
+impl RegExp {
+    pub fn parse(pattern: &str) -> Result<RegExp> {
+        let mut parser = PatternParser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let reg = parser.parse_alt()?;
+        if let Some(ch) = parser.peek() {
+            anyhow::bail!("Unexpected '{}' at {}", ch, parser.pos);
+        }
+        Ok(reg)
+    }
+
+    // 結合の強さ。Orが最も弱く、文字などの原子が最も強い
+    fn precedence(&self) -> u8 {
+        match self {
+            RegExp::Or { .. } => 0,
+            RegExp::Seq { .. } => 1,
+            RegExp::Repeat(_) | RegExp::Plus(_) | RegExp::Optional(_) | RegExp::RepeatN { .. } => 2,
+            _ => 3,
+        }
+    }
+
+    fn fmt_with(&self, f: &mut fmt::Formatter, precedence: u8) -> fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "(")?;
+            self.fmt_with(f, 0)?;
+            return write!(f, ")");
+        }
+        match self {
+            RegExp::Char(c) => {
+                if "\\.|*+?()[]{}^$".contains(*c) {
+                    write!(f, "\\")?;
+                }
+                write!(f, "{}", c)
+            }
+            RegExp::Any => write!(f, "."),
+            RegExp::Empty => write!(f, "()"),
+            // 範囲を1つも持たない文字クラスは何にもマッチしない
+            RegExp::Never => write!(f, "[]"),
+            RegExp::Start => write!(f, "^"),
+            RegExp::End => write!(f, "$"),
+            RegExp::Seq { left, right } => {
+                left.fmt_with(f, 1)?;
+                right.fmt_with(f, 1)
+            }
+            RegExp::Or { left, right } => {
+                left.fmt_with(f, 0)?;
+                write!(f, "|")?;
+                right.fmt_with(f, 0)
+            }
+            RegExp::Repeat(reg) => {
+                reg.fmt_with(f, 3)?;
+                write!(f, "*")
+            }
+            RegExp::Plus(reg) => {
+                reg.fmt_with(f, 3)?;
+                write!(f, "+")
+            }
+            RegExp::Optional(reg) => {
+                reg.fmt_with(f, 3)?;
+                write!(f, "?")
+            }
+            RegExp::RepeatN { inner, min, max } => {
+                inner.fmt_with(f, 3)?;
+                match max {
+                    Some(max) if max == min => write!(f, "{{{}}}", min),
+                    Some(max) => write!(f, "{{{},{}}}", min, max),
+                    None => write!(f, "{{{},}}", min),
+                }
+            }
+            RegExp::Class { ranges, negated } => {
+                write!(f, "[")?;
+                if *negated {
+                    write!(f, "^")?;
+                }
+                for (low, high) in ranges {
+                    write_class_char(f, *low)?;
+                    if low != high {
+                        write!(f, "-")?;
+                        write_class_char(f, *high)?;
+                    }
+                }
+                write!(f, "]")
+            }
+        }
+    }
+}
+
+fn write_class_char(f: &mut fmt::Formatter, ch: char) -> fmt::Result {
+    if "\\]^-".contains(ch) {
+        write!(f, "\\")?;
+    }
+    write!(f, "{}", ch)
+}
+
+impl fmt::Display for RegExp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.fmt_with(f, 0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::{
+        collections::HashSet,
+        time::{Duration, Instant},
+    };
+
+    use anyhow::Result;
+
     use super::RegExp;
 
     #[test]
@@ -232,4 +865,581 @@ mod tests {
         let regexp = RegExp::Empty;
         assert_eq!(regexp._match("", 0), Some([0].iter().copied().collect()));
     }
+
+    #[test]
+    fn test_regexp_plus1() {
+        let regexp = RegExp::Plus(Box::new(RegExp::Char('a')));
+        assert_eq!(regexp._match("a", 0), Some([1].iter().copied().collect()));
+    }
+
+    #[test]
+    fn test_regexp_plus2() {
+        let regexp = RegExp::Plus(Box::new(RegExp::Char('a')));
+        assert_eq!(
+            regexp._match("aaa", 0),
+            Some([1, 2, 3].iter().copied().collect())
+        );
+    }
+
+    #[test]
+    fn test_regexp_plus3() {
+        let regexp = RegExp::Plus(Box::new(RegExp::Char('a')));
+        assert_eq!(regexp._match("", 0), None);
+        assert_eq!(regexp._match("b", 0), None);
+        assert_eq!(regexp._match("ab", 1), None);
+    }
+
+    #[test]
+    fn test_regexp_optional1() {
+        let regexp = RegExp::Optional(Box::new(RegExp::Char('a')));
+        assert_eq!(
+            regexp._match("a", 0),
+            Some([0, 1].iter().copied().collect())
+        );
+        assert_eq!(regexp._match("b", 0), Some([0].iter().copied().collect()));
+    }
+
+    #[test]
+    fn test_regexp_optional2() {
+        let regexp = RegExp::Seq {
+            left: Box::new(RegExp::Optional(Box::new(RegExp::Char('a')))),
+            right: Box::new(RegExp::Char('b')),
+        };
+        assert_eq!(regexp._match("b", 0), Some([1].iter().copied().collect()));
+        assert_eq!(regexp._match("ab", 0), Some([2].iter().copied().collect()));
+        assert_eq!(regexp._match("aa", 0), None);
+    }
+
+    #[test]
+    fn test_regexp_class1() {
+        let regexp = RegExp::Class {
+            ranges: vec![('a', 'c')],
+            negated: false,
+        };
+        assert_eq!(regexp._match("b", 0), Some([1].iter().copied().collect()));
+        assert_eq!(regexp._match("d", 0), None);
+    }
+
+    #[test]
+    fn test_regexp_class2() {
+        let regexp = RegExp::Class {
+            ranges: vec![('0', '9')],
+            negated: true,
+        };
+        assert_eq!(regexp._match("5", 0), None);
+        assert_eq!(regexp._match("x", 0), Some([1].iter().copied().collect()));
+    }
+
+    #[test]
+    fn test_regexp_class3() {
+        let regexp = RegExp::Class {
+            ranges: vec![('a', 'c'), ('x', 'z')],
+            negated: false,
+        };
+        assert_eq!(regexp._match("ay", 1), Some([2].iter().copied().collect()));
+        assert_eq!(regexp._match("a", 1), None);
+        assert_eq!(
+            RegExp::Class {
+                ranges: vec![('a', 'c')],
+                negated: true,
+            }
+            ._match("a", 1),
+            None
+        );
+    }
+
+    #[test]
+    fn test_regexp_repeat_n1() {
+        let regexp = RegExp::RepeatN {
+            inner: Box::new(RegExp::Char('a')),
+            min: 2,
+            max: Some(3),
+        };
+        assert_eq!(
+            regexp._match("aaaa", 0),
+            Some([2, 3].iter().copied().collect())
+        );
+        assert_eq!(regexp._match("aaaa", 3), None);
+        assert_eq!(
+            regexp._match("aaaa", 2),
+            Some([4].iter().copied().collect())
+        );
+    }
+
+    #[test]
+    fn test_regexp_repeat_n2() {
+        let regexp = RegExp::RepeatN {
+            inner: Box::new(RegExp::Char('a')),
+            min: 0,
+            max: None,
+        };
+        assert_eq!(
+            regexp._match("aaab", 0),
+            Some([0, 1, 2, 3].iter().copied().collect())
+        );
+        assert_eq!(regexp._match("b", 0), Some([0].iter().copied().collect()));
+    }
+
+    #[test]
+    fn test_regexp_repeat_n3() {
+        // 空文字列に一致しうる中身でも停止する
+        let regexp = RegExp::RepeatN {
+            inner: Box::new(RegExp::Optional(Box::new(RegExp::Char('a')))),
+            min: 1,
+            max: None,
+        };
+        assert_eq!(
+            regexp._match("aa", 0),
+            Some([0, 1, 2].iter().copied().collect())
+        );
+        let regexp = RegExp::RepeatN {
+            inner: Box::new(RegExp::Empty),
+            min: 3,
+            max: None,
+        };
+        assert_eq!(regexp._match("a", 0), Some([0].iter().copied().collect()));
+    }
+
+    #[test]
+    fn test_regexp_repeat_multichar() {
+        let regexp = RegExp::Repeat(Box::new(RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Char('b')),
+        }));
+        assert_eq!(
+            regexp._match("abab", 0),
+            Some([0, 2, 4].iter().copied().collect())
+        );
+    }
+
+    #[test]
+    fn test_regexp_pathological() {
+        // (a*)*b
+        let regexp = RegExp::Seq {
+            left: Box::new(RegExp::Repeat(Box::new(RegExp::Repeat(Box::new(
+                RegExp::Char('a'),
+            ))))),
+            right: Box::new(RegExp::Char('b')),
+        };
+        let started = Instant::now();
+        let input = "a".repeat(200);
+        assert_eq!(regexp._match(&input, 0), None);
+        let input = format!("{}b", input);
+        assert_eq!(
+            regexp._match(&input, 0),
+            Some([201].iter().copied().collect())
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_regexp_is_full_match() {
+        // ab*
+        let regexp = RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Repeat(Box::new(RegExp::Char('b')))),
+        };
+        assert!(regexp.is_full_match("a"));
+        assert!(regexp.is_full_match("abbb"));
+        // "abc" は先頭の "ab" にしか一致しない
+        assert_eq!(
+            regexp._match("abc", 0),
+            Some([1, 2].iter().copied().collect())
+        );
+        assert!(!regexp.is_full_match("abc"));
+        assert!(!regexp.is_full_match("ba"));
+        assert!(!regexp.is_full_match(""));
+    }
+
+    #[test]
+    fn test_regexp_start() {
+        assert_eq!(
+            RegExp::Start._match("a", 0),
+            Some([0].iter().copied().collect())
+        );
+        assert_eq!(RegExp::Start._match("a", 1), None);
+        let regexp = RegExp::Seq {
+            left: Box::new(RegExp::Start),
+            right: Box::new(RegExp::Char('a')),
+        };
+        assert_eq!(regexp._match("aa", 0), Some([1].iter().copied().collect()));
+        assert_eq!(regexp._match("aa", 1), None);
+    }
+
+    #[test]
+    fn test_regexp_end() {
+        assert_eq!(
+            RegExp::End._match("a", 1),
+            Some([1].iter().copied().collect())
+        );
+        assert_eq!(RegExp::End._match("a", 0), None);
+        // ^a$
+        let regexp = RegExp::Seq {
+            left: Box::new(RegExp::Start),
+            right: Box::new(RegExp::Seq {
+                left: Box::new(RegExp::Char('a')),
+                right: Box::new(RegExp::End),
+            }),
+        };
+        assert_eq!(regexp._match("a", 0), Some([1].iter().copied().collect()));
+        assert_eq!(regexp._match("ab", 0), None);
+        assert_eq!(regexp._match("ba", 1), None);
+    }
+
+    // alphabet上の長さmax_len以下のすべての文字列
+    fn all_strings(alphabet: &[char], max_len: usize) -> Vec<String> {
+        let mut result = vec![String::new()];
+        let mut layer = vec![String::new()];
+        for _ in 0..max_len {
+            layer = layer
+                .iter()
+                .flat_map(|s| alphabet.iter().map(move |ch| format!("{}{}", s, ch)))
+                .collect();
+            result.extend(layer.iter().cloned());
+        }
+        result
+    }
+
+    #[test]
+    fn test_regexp_parse() {
+        assert_eq!(
+            RegExp::parse("a|bc*").unwrap(),
+            RegExp::Or {
+                left: Box::new(RegExp::Char('a')),
+                right: Box::new(RegExp::Seq {
+                    left: Box::new(RegExp::Char('b')),
+                    right: Box::new(RegExp::Repeat(Box::new(RegExp::Char('c')))),
+                }),
+            }
+        );
+        assert_eq!(
+            RegExp::parse("[^a-c.]").unwrap(),
+            RegExp::Class {
+                ranges: vec![('a', 'c'), ('.', '.')],
+                negated: true,
+            }
+        );
+        assert!(RegExp::parse("(ab").is_err());
+        assert!(RegExp::parse("*a").is_err());
+        assert!(RegExp::parse("a{2").is_err());
+    }
+
+    #[test]
+    fn test_regexp_display() {
+        let regexp = RegExp::Seq {
+            left: Box::new(RegExp::Or {
+                left: Box::new(RegExp::Char('a')),
+                right: Box::new(RegExp::Empty),
+            }),
+            right: Box::new(RegExp::Repeat(Box::new(RegExp::Seq {
+                left: Box::new(RegExp::Char('*')),
+                right: Box::new(RegExp::Any),
+            }))),
+        };
+        assert_eq!(regexp.to_string(), r"(a|())(\*.)*");
+
+        let patterns = [
+            "a(b|c)*",
+            "(a|)b",
+            "ab|a*",
+            "(ab)+c?",
+            "a{2,3}|b{1,}|c{2}",
+            "[a-b]*[^a]",
+            r"\**a",
+            "^a.$",
+            "((a|b)c)*",
+        ];
+        let inputs = all_strings(&['a', 'b', 'c', '*'], 4);
+        for pattern in patterns {
+            let regexp = RegExp::parse(pattern).unwrap();
+            let reparsed = RegExp::parse(&regexp.to_string()).unwrap();
+            for input in &inputs {
+                assert_eq!(
+                    regexp.is_full_match(input),
+                    reparsed.is_full_match(input),
+                    "{} and {} differ on {:?}",
+                    pattern,
+                    regexp,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_regexp_match_ci() {
+        // abc
+        let regexp = RegExp::parse("abc").unwrap();
+        assert_eq!(regexp._match("ABC", 0), None);
+        assert_eq!(
+            regexp.match_ci("ABC", 0),
+            Some([3].iter().copied().collect())
+        );
+        assert_eq!(
+            regexp.match_ci("aBc", 0),
+            Some([3].iter().copied().collect())
+        );
+
+        let regexp = RegExp::parse("(x|y)*[a-c]+").unwrap();
+        assert_eq!(
+            regexp.match_ci("XyXbA", 0),
+            Some([4, 5].iter().copied().collect())
+        );
+
+        let regexp = RegExp::parse("[^a-c]").unwrap();
+        assert_eq!(regexp.match_ci("B", 0), None);
+        assert_eq!(regexp.match_ci("D", 0), Some([1].iter().copied().collect()));
+    }
+
+    #[test]
+    fn test_regexp_shorthand_classes() {
+        let regexp = RegExp::parse(r"\d\d").unwrap();
+        assert!(regexp.is_full_match("42"));
+        assert!(!regexp.is_full_match("4a"));
+
+        let regexp = RegExp::parse(r"\w+").unwrap();
+        assert!(regexp.is_full_match("snake_case_1"));
+        assert!(!regexp.is_full_match("kebab-case"));
+
+        let regexp = RegExp::parse(r"a\sb").unwrap();
+        assert!(regexp.is_full_match("a b"));
+        assert!(regexp.is_full_match("a\tb"));
+        assert!(!regexp.is_full_match("a_b"));
+
+        let regexp = RegExp::parse(r"\D\W\S").unwrap();
+        assert!(regexp.is_full_match("a-b"));
+        assert!(!regexp.is_full_match("1-b"));
+        assert!(!regexp.is_full_match("a_b"));
+        assert!(!regexp.is_full_match("a- "));
+
+        let regexp = RegExp::parse(r"[\d_]+").unwrap();
+        assert!(regexp.is_full_match("1_000"));
+        assert!(RegExp::parse(r"[\D]").is_err());
+    }
+
+    #[test]
+    fn test_regexp_multibyte() {
+        let regexp = RegExp::Seq {
+            left: Box::new(RegExp::Char('あ')),
+            right: Box::new(RegExp::Char('b')),
+        };
+        assert_eq!(regexp._match("あb", 0), Some([2].iter().copied().collect()));
+        assert!(regexp.is_full_match("あb"));
+        assert_eq!(RegExp::Any._match("あ", 1), None);
+        assert_eq!(
+            RegExp::End._match("あい", 2),
+            Some([2].iter().copied().collect())
+        );
+        assert_eq!(RegExp::Empty._match("あ", 2), None);
+        assert!(RegExp::parse("[あ-お]+").unwrap().is_full_match("いえ"));
+    }
+
+    #[test]
+    fn test_alphabet() {
+        let regexp = RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Or {
+                left: Box::new(RegExp::Char('b')),
+                right: Box::new(RegExp::Char('c')),
+            }),
+        };
+        assert_eq!(regexp.alphabet(), HashSet::from(['a', 'b', 'c']));
+        assert_eq!(
+            RegExp::parse("x[0-2].*").unwrap().alphabet(),
+            HashSet::from(['x', '0', '1', '2'])
+        );
+    }
+
+    #[test]
+    fn test_match_budget() -> Result<()> {
+        let regexp = RegExp::parse("(a|b)*c")?;
+        let mut steps = 0;
+        assert_eq!(
+            regexp._match_budget("abc", 0, &mut steps, 1000)?,
+            Some(HashSet::from([3]))
+        );
+        assert!(steps > 0);
+
+        // 入れ子の繰り返しは左の一致位置ごとに右を試すので、入力長の2乗に近い回数になる
+        let regexp = RegExp::parse("((a*)*)*b")?;
+        let input = "a".repeat(200);
+        let mut steps = 0;
+        let err = regexp
+            ._match_budget(&input, 0, &mut steps, 10_000)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "match budget of 10000 steps exceeded");
+        Ok(())
+    }
+
+    #[test]
+    fn test_never() -> Result<()> {
+        for input in ["", "a", "ab"] {
+            for pos in 0..=input.len() {
+                assert_eq!(RegExp::Never._match(input, pos), None);
+            }
+        }
+
+        let r = RegExp::parse("a(b|c)*")?;
+        let or_never = RegExp::Or {
+            left: Box::new(r.clone()),
+            right: Box::new(RegExp::Never),
+        };
+        let never_seq = RegExp::Seq {
+            left: Box::new(RegExp::Never),
+            right: Box::new(r.clone()),
+        };
+        let seq_never = RegExp::Seq {
+            left: Box::new(r.clone()),
+            right: Box::new(RegExp::Never),
+        };
+        for input in all_strings(&['a', 'b', 'c'], 4) {
+            for pos in 0..=input.len() {
+                assert_eq!(or_never._match(&input, pos), r._match(&input, pos));
+                assert_eq!(never_seq._match(&input, pos), None);
+                assert_eq!(seq_never._match(&input, pos), None);
+            }
+        }
+        assert_eq!(RegExp::Empty._match("", 0), Some(HashSet::from([0])));
+        assert_eq!(RegExp::Never.to_string(), "[]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_longest_match() -> Result<()> {
+        let regexp = RegExp::Repeat(Box::new(RegExp::Char('a')));
+        assert_eq!(regexp.longest_match("aaab", 0), Some(3));
+        assert_eq!(regexp.longest_match("aaab", 3), Some(3));
+
+        let regexp = RegExp::parse("ab|abab|a")?;
+        assert_eq!(regexp.longest_match("ababa", 0), Some(4));
+        assert_eq!(regexp.longest_match("ababa", 1), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compile() -> Result<()> {
+        let patterns = [
+            "a(b|c)*d", ".*x", "[^ab]+", "(ab)?c+", "a.b", "[a-c]*|z", "[]",
+        ];
+        let inputs = [
+            "", "a", "ad", "abcd", "abxd", "x", "zzx", "xz", "cc", "abc", "abcc", "zé", "aéb",
+            "acb", "ab", "z", "\0", "\0x",
+        ];
+        for pattern in patterns {
+            let regexp = RegExp::parse(pattern)?;
+            let compiled = regexp.compile()?;
+            for input in inputs {
+                assert_eq!(
+                    compiled.is_match(input),
+                    regexp.is_full_match(input),
+                    "{} on {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+        assert!(RegExp::parse("^a")?.compile().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeat_nullable_inner() -> Result<()> {
+        // 空文字列に一致する部分式の繰り返しは、新しい位置が増えなくなった時点で止まる
+        let regexp = RegExp::Repeat(Box::new(RegExp::Empty));
+        assert_eq!(regexp._match("aaa", 0), Some(HashSet::from([0])));
+        assert_eq!(regexp._match("aaa", 3), Some(HashSet::from([3])));
+        assert!(regexp.is_full_match(""));
+        assert!(!regexp.is_full_match("a"));
+
+        let regexp = RegExp::Repeat(Box::new(RegExp::Or {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Empty),
+        }));
+        assert_eq!(regexp._match("aab", 0), Some(HashSet::from([0, 1, 2])));
+        assert_eq!(regexp._match("aab", 2), Some(HashSet::from([2])));
+        assert!(regexp.is_full_match("aaaa"));
+        assert!(!regexp.is_full_match("aab"));
+
+        let input = "a".repeat(40);
+        for pattern in ["(a?)*", "(a|)+", "(a?){2,}", "((a*)*)*"] {
+            let regexp = RegExp::parse(pattern)?;
+            assert!(regexp.is_full_match(&input), "{}", pattern);
+            assert!(regexp.is_full_match(""), "{}", pattern);
+            assert!(!regexp.is_full_match("ab"), "{}", pattern);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_equivalent() -> Result<()> {
+        let factored = RegExp::parse("a(b|c)")?;
+        assert!(factored.equivalent(&RegExp::parse("ab|ac")?)?);
+        assert!(!factored.equivalent(&RegExp::parse("ab")?)?);
+        assert!(RegExp::parse("(a|b)*")?.equivalent(&RegExp::parse("(a*b*)*")?)?);
+        assert!(RegExp::parse("a+")?.equivalent(&RegExp::parse("aa*")?)?);
+
+        // Anyはパターンに現れない文字にも一致する
+        assert!(!RegExp::parse(".")?.equivalent(&RegExp::parse("[ab]")?)?);
+        assert!(RegExp::parse("[^a]|a")?.equivalent(&RegExp::parse(".")?)?);
+        assert!(RegExp::parse("^a")?
+            .equivalent(&RegExp::parse("a")?)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_seq_and_alt() {
+        let (a, b, c) = (RegExp::Char('a'), RegExp::Char('b'), RegExp::Char('c'));
+        let seq = RegExp::seq(vec![a.clone(), b.clone(), c.clone()]);
+        assert_eq!(
+            seq,
+            RegExp::Seq {
+                left: Box::new(a.clone()),
+                right: Box::new(RegExp::Seq {
+                    left: Box::new(b.clone()),
+                    right: Box::new(c.clone()),
+                }),
+            }
+        );
+        let alt = RegExp::alt(vec![a.clone(), b.clone(), c.clone()]);
+        assert_eq!(
+            alt,
+            RegExp::Or {
+                left: Box::new(a.clone()),
+                right: Box::new(RegExp::Or {
+                    left: Box::new(b.clone()),
+                    right: Box::new(c.clone()),
+                }),
+            }
+        );
+        for input in ["", "a", "b", "c", "ab", "abc", "cba", "abcd"] {
+            assert_eq!(seq.is_full_match(input), input == "abc", "{:?}", input);
+            assert_eq!(alt.is_full_match(input), input.len() == 1, "{:?}", input);
+        }
+
+        assert_eq!(RegExp::seq(vec![]), RegExp::Empty);
+        assert_eq!(RegExp::alt(vec![]), RegExp::Never);
+        assert_eq!(RegExp::seq(vec![a.clone()]), a);
+        assert_eq!(RegExp::alt(vec![b.clone()]), b);
+    }
+
+    #[test]
+    fn test_literal() {
+        let regexp = RegExp::literal("abc");
+        assert_eq!(
+            regexp,
+            RegExp::seq(vec![
+                RegExp::Char('a'),
+                RegExp::Char('b'),
+                RegExp::Char('c')
+            ])
+        );
+        assert!(regexp.is_full_match("abc"));
+        for input in ["", "a", "ab", "abcc", "abd", "xabc", "ABC"] {
+            assert!(!regexp.is_full_match(input), "{:?}", input);
+        }
+        // 特殊文字もそのまま1文字として扱う
+        assert!(RegExp::literal("a*(b)").is_full_match("a*(b)"));
+        assert_eq!(RegExp::literal(""), RegExp::Empty);
+    }
 }
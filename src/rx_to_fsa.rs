@@ -1,5 +1,7 @@
 use std::collections::{HashMap, HashSet};
 
+use anyhow::{Context, Result};
+
 use crate::{
     fsa::{EpsilonTransition, NFATransition, State, NFA},
     regexp::RegExp,
@@ -9,11 +11,22 @@ pub struct NFAConstructor {
     state_counter: State,
 }
 
+impl Default for NFAConstructor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NFAConstructor {
     pub fn new() -> Self {
         Self { state_counter: 0 }
     }
 
+    // 状態番号はresetするまで単調に増え続けるので、独立したNFAを作るときはresetしてから呼ぶ
+    pub fn reset(&mut self) {
+        self.state_counter = 0;
+    }
+
     pub fn new_state(&mut self) -> State {
         self.state_counter += 1;
         self.state_counter
@@ -22,9 +35,9 @@ impl NFAConstructor {
     pub fn simple_dict_union(&self, d1: &NFATransition, d2: &NFATransition) -> NFATransition {
         let mut result = NFATransition::new();
         for (state, trans) in d1.iter().chain(d2) {
-            let entry = result.entry(*state).or_insert_with(HashMap::new);
+            let entry = result.entry(*state).or_default();
             for (ch, states) in trans {
-                let entry_set = entry.entry(*ch).or_insert_with(HashSet::new);
+                let entry_set = entry.entry(*ch).or_default();
                 entry_set.extend(states.iter().cloned());
             }
         }
@@ -34,7 +47,7 @@ impl NFAConstructor {
     pub fn eps_union(&self, e1: &EpsilonTransition, e2: &EpsilonTransition) -> EpsilonTransition {
         let mut result = EpsilonTransition::new();
         for (state, states) in e1.iter().chain(e2) {
-            let entry = result.entry(*state).or_insert_with(HashSet::new);
+            let entry = result.entry(*state).or_default();
             entry.extend(states.iter().cloned());
         }
         result
@@ -52,20 +65,31 @@ impl NFAConstructor {
         }
     }
 
-    pub fn rx_to_nfa(&mut self, rx: &RegExp, alphabet: &HashSet<char>) -> Option<NFA> {
+    fn single_final(&self, n: &NFA, construction: &str) -> Result<State> {
+        self.end_state(n).with_context(|| {
+            format!(
+                "expected single final state during {} construction, found {:?}",
+                construction, n.finals
+            )
+        })
+    }
+
+    pub fn rx_to_nfa(&mut self, rx: &RegExp, alphabet: &HashSet<char>) -> Result<NFA> {
         match rx {
             RegExp::Char(ch) => {
                 let start = self.new_state();
                 let end = self.new_state();
                 let mut trans = NFATransition::new();
                 trans.insert(start, HashMap::from([(*ch, HashSet::from([end]))]));
-                Some(NFA {
+                Ok(NFA {
                     transition: trans,
                     epsilon_transition: EpsilonTransition::new(),
                     start,
                     finals: HashSet::from([end]),
                 })
             }
+            // '.'はalphabetの各文字への遷移に展開する。alphabetにない文字は、
+            // このNFAでもto_dfaで得られるDFAでも一致しない
             RegExp::Any => {
                 let start = self.new_state();
                 let end = self.new_state();
@@ -75,17 +99,46 @@ impl NFAConstructor {
                     state_trans.insert(ch, HashSet::from([end]));
                 }
                 trans.insert(start, state_trans);
-                Some(NFA {
+                Ok(NFA {
+                    transition: trans,
+                    epsilon_transition: EpsilonTransition::new(),
+                    start,
+                    finals: HashSet::from([end]),
+                })
+            }
+            RegExp::Class { ranges, negated } => {
+                let start = self.new_state();
+                let end = self.new_state();
+                let mut trans = NFATransition::new();
+                let mut state_trans = HashMap::new();
+                for &ch in alphabet {
+                    if RegExp::class_contains(ranges, *negated, ch) {
+                        state_trans.insert(ch, HashSet::from([end]));
+                    }
+                }
+                trans.insert(start, state_trans);
+                Ok(NFA {
                     transition: trans,
                     epsilon_transition: EpsilonTransition::new(),
                     start,
                     finals: HashSet::from([end]),
                 })
             }
+            // 受理状態へ到達する遷移を持たないので何も受理しない
+            RegExp::Never => {
+                let start = self.new_state();
+                let end = self.new_state();
+                Ok(NFA {
+                    transition: NFATransition::new(),
+                    epsilon_transition: EpsilonTransition::new(),
+                    start,
+                    finals: HashSet::from([end]),
+                })
+            }
             RegExp::Empty => {
                 let start = self.new_state();
                 let finals = HashSet::from([start]);
-                Some(NFA {
+                Ok(NFA {
                     transition: NFATransition::new(),
                     epsilon_transition: EpsilonTransition::new(),
                     start,
@@ -95,12 +148,12 @@ impl NFAConstructor {
             RegExp::Seq { left, right } => {
                 let l_nfa = self.rx_to_nfa(left, alphabet)?;
                 let r_nfa = self.rx_to_nfa(right, alphabet)?;
-                let l_end = self.end_state(&l_nfa)?;
+                let l_end = self.single_final(&l_nfa, "Seq")?;
                 let eps_trans = self.eps_union(
                     &l_nfa.epsilon_transition,
                     &HashMap::from([(l_end, HashSet::from([r_nfa.start]))]),
                 );
-                Some(NFA {
+                Ok(NFA {
                     transition: self.nfa_trans_union(&l_nfa.transition, &r_nfa.transition),
                     epsilon_transition: self.eps_union(&eps_trans, &r_nfa.epsilon_transition),
                     start: l_nfa.start,
@@ -114,10 +167,10 @@ impl NFAConstructor {
                 let end = self.new_state();
                 let eps_trans = HashMap::from([
                     (start, HashSet::from([l_nfa.start, r_nfa.start])),
-                    (self.end_state(&l_nfa)?, HashSet::from([end])),
-                    (self.end_state(&r_nfa)?, HashSet::from([end])),
+                    (self.single_final(&l_nfa, "Or")?, HashSet::from([end])),
+                    (self.single_final(&r_nfa, "Or")?, HashSet::from([end])),
                 ]);
-                Some(NFA {
+                Ok(NFA {
                     transition: self.nfa_trans_union(&l_nfa.transition, &r_nfa.transition),
                     epsilon_transition: self.eps_union(
                         &eps_trans,
@@ -131,29 +184,72 @@ impl NFAConstructor {
                 let start = self.new_state();
                 let reg_nfa = self.rx_to_nfa(reg, alphabet)?;
                 let end = self.new_state();
-                let reg_end = self.end_state(&reg_nfa)?;
+                let reg_end = self.single_final(&reg_nfa, "Repeat")?;
                 let eps_trans = HashMap::from([
                     (start, HashSet::from([reg_nfa.start, end])),
                     (reg_end, HashSet::from([reg_nfa.start, end])),
                 ]);
-                Some(NFA {
+                Ok(NFA {
                     transition: reg_nfa.transition,
                     epsilon_transition: self.eps_union(&eps_trans, &reg_nfa.epsilon_transition),
                     start,
                     finals: HashSet::from([end]),
                 })
             }
+            RegExp::Plus(reg) => {
+                // Repeatから開始状態→終了状態のε遷移を除いたもの
+                let start = self.new_state();
+                let reg_nfa = self.rx_to_nfa(reg, alphabet)?;
+                let end = self.new_state();
+                let reg_end = self.single_final(&reg_nfa, "Plus")?;
+                let eps_trans = HashMap::from([
+                    (start, HashSet::from([reg_nfa.start])),
+                    (reg_end, HashSet::from([reg_nfa.start, end])),
+                ]);
+                Ok(NFA {
+                    transition: reg_nfa.transition,
+                    epsilon_transition: self.eps_union(&eps_trans, &reg_nfa.epsilon_transition),
+                    start,
+                    finals: HashSet::from([end]),
+                })
+            }
+            RegExp::Optional(reg) => {
+                let start = self.new_state();
+                let reg_nfa = self.rx_to_nfa(reg, alphabet)?;
+                let end = self.new_state();
+                let reg_end = self.single_final(&reg_nfa, "Optional")?;
+                let eps_trans = HashMap::from([
+                    (start, HashSet::from([reg_nfa.start, end])),
+                    (reg_end, HashSet::from([end])),
+                ]);
+                Ok(NFA {
+                    transition: reg_nfa.transition,
+                    epsilon_transition: self.eps_union(&eps_trans, &reg_nfa.epsilon_transition),
+                    start,
+                    finals: HashSet::from([end]),
+                })
+            }
+            RegExp::RepeatN { .. } | RegExp::Start | RegExp::End => {
+                anyhow::bail!("{} is not supported by rx_to_nfa", rx)
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{fsa::State, regexp::RegExp, rx_to_fsa::NFAConstructor};
-    use std::collections::HashSet;
+    use anyhow::Result;
+    use proptest::prelude::*;
+
+    use crate::{
+        fsa::{State, NFA},
+        regexp::RegExp,
+        rx_to_fsa::NFAConstructor,
+    };
+    use std::collections::{HashMap, HashSet};
 
     #[test]
-    fn test_rx_to_nfa_and_nfa_to_dfa() {
+    fn test_rx_to_nfa_and_nfa_to_dfa() -> Result<()> {
         let mut nfa_constructor = NFAConstructor::new();
         let alphabet = HashSet::from(['a', 'b']);
 
@@ -165,9 +261,7 @@ mod tests {
             right: Box::new(RegExp::Char('b')),
         };
 
-        let nfa = nfa_constructor
-            .rx_to_nfa(&rx, &alphabet)
-            .expect("Failed to convert RegExp to NFA");
+        let nfa = nfa_constructor.rx_to_nfa(&rx, &alphabet)?;
         let dfa = nfa.to_dfa();
         let dfa_states: HashSet<State> = dfa
             .transition
@@ -180,5 +274,234 @@ mod tests {
             )
             .collect();
         assert_eq!(dfa_states.len(), 4, "DFA should have 4 states");
+        Ok(())
+    }
+
+    #[test]
+    fn test_rx_to_nfa_class() {
+        let mut nfa_constructor = NFAConstructor::new();
+        let alphabet = HashSet::from(['a', 'b', 'c', '1']);
+        let rx = RegExp::Repeat(Box::new(RegExp::Class {
+            ranges: vec![('a', 'b')],
+            negated: false,
+        }));
+        let dfa = nfa_constructor
+            .rx_to_nfa(&rx, &alphabet)
+            .expect("Failed to convert RegExp to NFA")
+            .to_dfa();
+        assert!(dfa.try_accept("abba"));
+        assert!(!dfa.try_accept("abc"));
+
+        let rx = RegExp::Class {
+            ranges: vec![('a', 'b')],
+            negated: true,
+        };
+        let dfa = nfa_constructor
+            .rx_to_nfa(&rx, &alphabet)
+            .expect("Failed to convert RegExp to NFA")
+            .to_dfa();
+        assert!(dfa.try_accept("c"));
+        assert!(dfa.try_accept("1"));
+        assert!(!dfa.try_accept("a"));
+    }
+
+    #[test]
+    fn test_rx_to_nfa_any() {
+        let mut nfa_constructor = NFAConstructor::new();
+        let alphabet = HashSet::from(['a', 'b']);
+        let rx = RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Any),
+        };
+        let nfa = nfa_constructor
+            .rx_to_nfa(&rx, &alphabet)
+            .expect("Failed to convert RegExp to NFA");
+        let dfa = nfa.to_dfa();
+        assert_eq!(dfa.alphabet(), alphabet);
+        assert_eq!(dfa.accepted_up_to(3), vec!["aa", "ab"]);
+        for input in ["aa", "ab", "ac", "a", "b", "aab"] {
+            assert_eq!(nfa.try_accept(input), dfa.try_accept(input));
+        }
+        assert!(!dfa.try_accept("ac"));
+    }
+
+    #[test]
+    fn test_rx_to_nfa_plus() {
+        let mut nfa_constructor = NFAConstructor::new();
+        let alphabet = HashSet::from(['a', 'b']);
+        let rx = RegExp::Plus(Box::new(RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Char('b')),
+        }));
+        let dfa = nfa_constructor
+            .rx_to_nfa(&rx, &alphabet)
+            .expect("Failed to convert RegExp to NFA")
+            .to_dfa();
+        assert!(dfa.try_accept("ab"));
+        assert!(dfa.try_accept("ababab"));
+        assert!(!dfa.try_accept(""));
+        assert!(!dfa.try_accept("aba"));
+    }
+
+    #[test]
+    fn test_rx_to_nfa_optional() {
+        let mut nfa_constructor = NFAConstructor::new();
+        let alphabet = HashSet::from(['a', 'b']);
+        let rx = RegExp::Seq {
+            left: Box::new(RegExp::Optional(Box::new(RegExp::Char('a')))),
+            right: Box::new(RegExp::Char('b')),
+        };
+        let dfa = nfa_constructor
+            .rx_to_nfa(&rx, &alphabet)
+            .expect("Failed to convert RegExp to NFA")
+            .to_dfa();
+        assert!(dfa.try_accept("b"));
+        assert!(dfa.try_accept("ab"));
+        assert!(!dfa.try_accept("aab"));
+        assert!(!dfa.try_accept("a"));
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut nfa_constructor = NFAConstructor::new();
+        let alphabet = HashSet::from(['a', 'b']);
+        let rx = RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Char('b')),
+        };
+        let first = nfa_constructor
+            .rx_to_nfa(&rx, &alphabet)
+            .expect("Failed to convert RegExp to NFA");
+        let second = nfa_constructor
+            .rx_to_nfa(&rx, &alphabet)
+            .expect("Failed to convert RegExp to NFA");
+        assert_eq!(first.start, 1);
+        assert_eq!(second.start, 5);
+
+        nfa_constructor.reset();
+        let third = nfa_constructor
+            .rx_to_nfa(&rx, &alphabet)
+            .expect("Failed to convert RegExp to NFA");
+        assert_eq!(third.start, 1);
+        assert_eq!(first, third);
+    }
+
+    #[test]
+    fn test_rx_to_nfa_errors() {
+        let mut nfa_constructor = NFAConstructor::new();
+        let alphabet = HashSet::from(['a']);
+        let err = nfa_constructor
+            .rx_to_nfa(&RegExp::Start, &alphabet)
+            .unwrap_err();
+        assert!(err.to_string().contains("not supported"));
+
+        let nfa = NFA {
+            transition: HashMap::new(),
+            epsilon_transition: HashMap::new(),
+            start: 0,
+            finals: HashSet::from([1, 2]),
+        };
+        let err = nfa_constructor.single_final(&nfa, "Seq").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expected single final state during Seq construction"));
+    }
+
+    #[test]
+    fn test_compile() -> Result<()> {
+        // a(b|ε)
+        let rx = RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Or {
+                left: Box::new(RegExp::Char('b')),
+                right: Box::new(RegExp::Empty),
+            }),
+        };
+        let compiled = rx.compile()?;
+        assert!(compiled.is_match("a"));
+        assert!(compiled.is_match("ab"));
+        assert!(!compiled.is_match(""));
+        assert!(!compiled.is_match("b"));
+        assert!(!compiled.is_match("abb"));
+
+        // Anyや否定クラスはパターンに現れない文字にもマッチする
+        let compiled = RegExp::Any.compile()?;
+        assert!(compiled.is_match("z"));
+        assert!(!compiled.is_match(""));
+        let rx = RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Class {
+                ranges: vec![('a', 'b')],
+                negated: true,
+            }),
+        };
+        let compiled = rx.compile()?;
+        assert!(compiled.is_match("az"));
+        assert!(compiled.is_match("aあ"));
+        assert!(!compiled.is_match("ab"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rx_to_nfa_never() -> Result<()> {
+        let rx = RegExp::Or {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Never),
+        };
+        let compiled = rx.compile()?;
+        assert!(compiled.is_match("a"));
+        assert!(!compiled.is_match(""));
+
+        let rx = RegExp::Seq {
+            left: Box::new(RegExp::Char('a')),
+            right: Box::new(RegExp::Never),
+        };
+        assert!(rx.compile()?.dfa().is_empty());
+        Ok(())
+    }
+
+    // 記号a,b,cの上の小さなRegExp。Start/EndとRepeatNはrx_to_nfaが扱わないので作らない
+    fn arb_regexp() -> impl Strategy<Value = RegExp> {
+        let leaf = prop_oneof![
+            prop::sample::select(vec!['a', 'b', 'c']).prop_map(RegExp::Char),
+            Just(RegExp::Any),
+            Just(RegExp::Empty),
+            Just(RegExp::Never),
+            (prop::sample::select(vec!['a', 'b']), any::<bool>()).prop_map(|(high, negated)| {
+                RegExp::Class {
+                    ranges: vec![('a', high)],
+                    negated,
+                }
+            }),
+        ];
+        leaf.prop_recursive(4, 16, 2, |inner| {
+            prop_oneof![
+                (inner.clone(), inner.clone()).prop_map(|(left, right)| RegExp::Seq {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }),
+                (inner.clone(), inner.clone()).prop_map(|(left, right)| RegExp::Or {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                }),
+                inner.clone().prop_map(|rx| RegExp::Repeat(Box::new(rx))),
+                inner.clone().prop_map(|rx| RegExp::Plus(Box::new(rx))),
+                inner.prop_map(|rx| RegExp::Optional(Box::new(rx))),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_regexp_matches_dfa(
+            rx in arb_regexp(),
+            inputs in prop::collection::vec("[abc]{0,6}", 1..16),
+        ) {
+            let compiled = rx.compile().unwrap();
+            for input in &inputs {
+                let expected = rx.is_full_match(input);
+                prop_assert_eq!(compiled.is_match(input), expected, "{} on {:?}", rx, input);
+            }
+        }
     }
 }
@@ -1,22 +1,49 @@
-use crate::tokens::Token;
+use anyhow::Result;
+
+use crate::{
+    ast::IntVal,
+    regexp::{CompiledRegExp, RegExp},
+    tokens::{Keyword, Operator, Punct, Token},
+};
 
 pub struct TokenIterator {
     input: String,
     eof: bool,
+    peeked: Option<Option<Token>>,
+    preserve_trivia: bool,
 }
 
-impl Iterator for TokenIterator {
-    type Item = Token;
+impl TokenIterator {
+    // 有効にすると空白とコメントを読み捨てずにWhitespaceとCommentとして返す。
+    // すべてのトークンのtextをつなげると元の入力に戻る
+    pub fn preserve_trivia(mut self, preserve: bool) -> Self {
+        self.preserve_trivia = preserve;
+        self
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    // 次のトークンを消費せずに覗き見る
+    pub fn peek(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.scan());
+        }
+        self.peeked.as_ref().and_then(|token| token.as_ref())
+    }
+
+    fn scan(&mut self) -> Option<Token> {
         const SKIP: &str = r"([\s]*(//.*\n)?)*";
-        const GROUP1: &str = r"while\b|do\b|if\b|then\b|else\b|:=|[;{}<=+\-*/]";
+        const GROUP1: &str =
+            r"while\b|do\b|if\b|then\b|else\b|def\b|true\b|false\b|:=|[;,{}()<>=+\-*/]";
         const GROUP2: &str = r"[A-Za-z_][A-Za-z_0-9]*";
         const GROUP3: &str = r"[0-9]+";
 
         let regexp = format!(r"{}(({})|({})|({}))", SKIP, GROUP1, GROUP2, GROUP3);
         let pattern = regex::Regex::new(&regexp).ok()?;
 
+        if self.preserve_trivia {
+            if let Some(token) = self.scan_trivia() {
+                return Some(token);
+            }
+        }
         if !self.eof && self.input.trim().is_empty() {
             self.eof = true;
             return Some(Token::End);
@@ -24,34 +51,156 @@ impl Iterator for TokenIterator {
 
         if let Some(cap) = pattern.captures(&self.input.clone()) {
             let matched_length = cap.get(0).unwrap().end();
-            self.input = self.input[matched_length..]
-                .to_string()
-                .trim_start()
-                .to_string();
+            self.input = self.input[matched_length..].to_string();
+            if !self.preserve_trivia {
+                self.input = self.input.trim_start().to_string();
+            }
 
             if let Some(s) = cap.get(4).map(|m| m.as_str()) {
-                return Some(Token::KeyWord(s.to_string()));
+                return classify(s);
             }
             if let Some(s) = cap.get(5).map(|m| m.as_str()) {
                 return Some(Token::Identifier(s.to_string()));
             }
             if let Some(s) = cap.get(6).map(|m| m.as_str()) {
-                return Some(Token::Number(s.parse::<i32>().ok()?));
+                return Some(Token::Number(s.parse::<IntVal>().ok()?, s.to_string()));
             }
         }
         None
     }
+
+    // 先頭の空白の並び、または行末までのコメントを1つ切り出す
+    fn scan_trivia(&mut self) -> Option<Token> {
+        let rest = self.input.trim_start();
+        let (token, len) = if rest.len() < self.input.len() {
+            let len = self.input.len() - rest.len();
+            (Token::Whitespace(self.input[..len].to_string()), len)
+        } else if self.input.starts_with("//") {
+            let len = self.input.find('\n').unwrap_or(self.input.len());
+            (Token::Comment(self.input[..len].to_string()), len)
+        } else {
+            return None;
+        };
+        self.input = self.input[len..].to_string();
+        Some(token)
+    }
+}
+
+fn classify(symbol: &str) -> Option<Token> {
+    let token = match symbol {
+        "while" => Token::Keyword(Keyword::While),
+        "do" => Token::Keyword(Keyword::Do),
+        "if" => Token::Keyword(Keyword::If),
+        "then" => Token::Keyword(Keyword::Then),
+        "else" => Token::Keyword(Keyword::Else),
+        "def" => Token::Keyword(Keyword::Def),
+        "true" => Token::Keyword(Keyword::True),
+        "false" => Token::Keyword(Keyword::False),
+        ":=" => Token::Op(Operator::Assign),
+        "+" => Token::Op(Operator::Plus),
+        "-" => Token::Op(Operator::Minus),
+        "*" => Token::Op(Operator::Star),
+        "/" => Token::Op(Operator::Slash),
+        "<" => Token::Op(Operator::Lt),
+        ">" => Token::Op(Operator::Gt),
+        "=" => Token::Op(Operator::Eq),
+        ";" => Token::Punct(Punct::Semicolon),
+        "{" => Token::Punct(Punct::LBrace),
+        "}" => Token::Punct(Punct::RBrace),
+        "(" => Token::Punct(Punct::LParen),
+        ")" => Token::Punct(Punct::RParen),
+        "," => Token::Punct(Punct::Comma),
+        _ => return None,
+    };
+    Some(token)
+}
+
+impl Iterator for TokenIterator {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.peeked.take() {
+            Some(token) => token,
+            None => self.scan(),
+        }
+    }
 }
 
 pub fn tokenize(input: String) -> TokenIterator {
-    TokenIterator { input, eof: false }
+    TokenIterator {
+        input,
+        eof: false,
+        peeked: None,
+        preserve_trivia: false,
+    }
+}
+
+// 自前のRegExpから作ったDFAで字句解析する。キーワードは識別子より先に並べ、
+// 同じ長さで一致したときは先のクラスを優先するので\bは要らない
+pub fn tokenize_native(input: &str) -> Result<Vec<Token>> {
+    let dfas = [
+        r"while|do|if|then|else|def|true|false|:=|[;,{}()<>=+\-*/]",
+        r"[A-Za-z_][A-Za-z_0-9]*",
+        r"[0-9]+",
+    ]
+    .iter()
+    .map(|pattern| RegExp::parse(pattern)?.compile())
+    .collect::<Result<Vec<CompiledRegExp>>>()?;
+
+    let mut tokens = Vec::new();
+    let mut rest = skip_trivia(input);
+    while !rest.is_empty() {
+        let mut best: Option<(usize, usize)> = None;
+        for (class, dfa) in dfas.iter().enumerate() {
+            if let Some(len) = dfa.longest_prefix(rest) {
+                if best.is_none_or(|(_, best_len)| best_len < len) {
+                    best = Some((class, len));
+                }
+            }
+        }
+        let Some((class, len)) = best else {
+            let offset = input.len() - rest.len();
+            anyhow::bail!(
+                "Unexpected {:?} at {}",
+                rest.chars().next().unwrap(),
+                offset
+            );
+        };
+        let lexeme = &rest[..len];
+        let token = match class {
+            0 => classify(lexeme),
+            1 => Some(Token::Identifier(lexeme.to_string())),
+            _ => lexeme
+                .parse::<IntVal>()
+                .ok()
+                .map(|value| Token::Number(value, lexeme.to_string())),
+        };
+        tokens.push(token.ok_or_else(|| anyhow::anyhow!("Invalid token {:?}", lexeme))?);
+        rest = skip_trivia(&rest[len..]);
+    }
+    tokens.push(Token::End);
+    Ok(tokens)
+}
+
+// 空白と行末までのコメントを読み飛ばす
+fn skip_trivia(mut input: &str) -> &str {
+    loop {
+        input = input.trim_start();
+        match input.strip_prefix("//") {
+            Some(comment) => input = comment.find('\n').map_or("", |end| &comment[end..]),
+            None => return input,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
 
-    use crate::{scanner::tokenize, tokens::Token};
+    use crate::{
+        scanner::{tokenize, tokenize_native},
+        tokens::{Keyword, Operator, Punct, Token},
+    };
 
     #[test]
     fn test_last_token_kind() -> Result<()> {
@@ -63,9 +212,9 @@ mod tests {
         }
         ";
 
-        let mut iter = tokenize(sample.to_string());
+        let iter = tokenize(sample.to_string());
         let mut last = None;
-        while let Some(token) = iter.next() {
+        for token in iter {
             last = Some(token);
         }
         assert_eq!(last, Some(Token::End));
@@ -82,14 +231,14 @@ mod tests {
         }
         ";
 
-        let mut iter = tokenize(sample.to_string());
+        let iter = tokenize(sample.to_string());
         let mut last = None;
         let mut second_to_last = None;
-        while let Some(token) = iter.next() {
+        for token in iter {
             second_to_last = last;
             last = Some(token);
         }
-        assert_eq!(second_to_last, Some(Token::KeyWord("}".to_string())));
+        assert_eq!(second_to_last, Some(Token::Punct(Punct::RBrace)));
         Ok(())
     }
 
@@ -97,21 +246,157 @@ mod tests {
     fn test_comments() -> Result<()> {
         let sample2 = "
         {
-            i := 10;        // this is comment.
+            i := 010;       // this is comment.
             while i do
               i := i - 1
         }
         ";
 
-        let mut iter = tokenize(sample2.to_string());
+        let iter = tokenize(sample2.to_string());
         let mut last = None;
         let mut second_to_last = None;
-        while let Some(token) = iter.next() {
+        for token in iter {
             second_to_last = last;
             last = Some(token);
         }
-        assert_eq!(second_to_last, Some(Token::KeyWord("}".to_string())));
+        assert_eq!(second_to_last, Some(Token::Punct(Punct::RBrace)));
         assert_eq!(last, Some(Token::End));
         Ok(())
     }
+
+    #[test]
+    fn test_peek() -> Result<()> {
+        let mut iter = tokenize("i := 10".to_string());
+        assert_eq!(iter.peek(), Some(&Token::Identifier("i".to_string())));
+        assert_eq!(iter.peek(), Some(&Token::Identifier("i".to_string())));
+        assert_eq!(iter.next(), Some(Token::Identifier("i".to_string())));
+        assert_eq!(iter.peek(), Some(&Token::Op(Operator::Assign)));
+        assert_eq!(iter.next(), Some(Token::Op(Operator::Assign)));
+        assert_eq!(iter.next(), Some(Token::Number(10, "10".to_string())));
+        assert_eq!(iter.next(), Some(Token::End));
+        assert_eq!(iter.peek(), None);
+        assert_eq!(iter.next(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyword_token() -> Result<()> {
+        let mut iter = tokenize("while i do".to_string());
+        assert_eq!(iter.next(), Some(Token::Keyword(Keyword::While)));
+        assert_eq!(iter.next(), Some(Token::Identifier("i".to_string())));
+        assert_eq!(iter.next(), Some(Token::Keyword(Keyword::Do)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_operator_token() -> Result<()> {
+        let mut iter = tokenize("i := i - 1".to_string());
+        assert_eq!(iter.next(), Some(Token::Identifier("i".to_string())));
+        assert_eq!(iter.next(), Some(Token::Op(Operator::Assign)));
+        assert_eq!(iter.next(), Some(Token::Identifier("i".to_string())));
+        assert_eq!(iter.next(), Some(Token::Op(Operator::Minus)));
+        assert_eq!(iter.next(), Some(Token::Number(1, "1".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_tokenize_native() -> Result<()> {
+        let samples = [
+            "
+            {
+                i := 10;
+                while 0 < i do
+                  i := i - 1
+            }
+            ",
+            "
+            {
+                i := 010;       // this is comment.
+                while i do
+                  i := i - 1
+            }
+            ",
+            "
+            def sum(n) {
+                if n = 0 then return := 0 else return := n + sum(n - 1)
+            };
+            x := sum(4) * (2 / 1)
+            ",
+            "iff := dox; thenelse := while_ + def0",
+        ];
+        for sample in samples {
+            let expected: Vec<Token> = tokenize(sample.to_string()).collect();
+            assert_eq!(tokenize_native(sample)?, expected, "{}", sample);
+        }
+        assert!(tokenize_native("x := 1 # 2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_preserve_trivia() -> Result<()> {
+        let sample = "
+        {
+            i := 010;       // this is comment.
+            while 0 < i do  // trailing
+              i := i - 1
+        }  // last line without newline";
+
+        let tokens: Vec<Token> = tokenize(sample.to_string()).preserve_trivia(true).collect();
+        let text: String = tokens.iter().map(Token::text).collect();
+        assert_eq!(text, sample);
+        assert!(tokens.contains(&Token::Comment("// this is comment.".to_string())));
+        assert!(tokens.contains(&Token::Number(10, "010".to_string())));
+        assert_eq!(tokens.last(), Some(&Token::End));
+
+        // トリビアを取り除くと既定のトークン列と一致する
+        let significant: Vec<Token> = tokens
+            .into_iter()
+            .filter(|token| !matches!(token, Token::Comment(_) | Token::Whitespace(_)))
+            .collect();
+        let plain: Vec<Token> =
+            tokenize("{ i := 010; while 0 < i do i := i - 1 }".to_string()).collect();
+        assert_eq!(significant, plain);
+        Ok(())
+    }
+
+    #[test]
+    fn test_keyword_prefix_is_identifier() -> Result<()> {
+        // \bのおかげでキーワードで始まる識別子は分割されない
+        for word in ["whiles", "ifx", "doing"] {
+            let expected = vec![Token::Identifier(word.to_string()), Token::End];
+            assert_eq!(tokenize(word.to_string()).collect::<Vec<_>>(), expected);
+            assert_eq!(tokenize_native(word)?, expected);
+        }
+        let mut iter = tokenize("while whiles do doing".to_string());
+        assert_eq!(iter.next(), Some(Token::Keyword(Keyword::While)));
+        assert_eq!(iter.next(), Some(Token::Identifier("whiles".to_string())));
+        assert_eq!(iter.next(), Some(Token::Keyword(Keyword::Do)));
+        assert_eq!(iter.next(), Some(Token::Identifier("doing".to_string())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bool_keywords() -> Result<()> {
+        let src = "if true then x := 1 else x := falsey";
+        let expected = vec![
+            Token::Keyword(Keyword::If),
+            Token::Keyword(Keyword::True),
+            Token::Keyword(Keyword::Then),
+            Token::Identifier("x".to_string()),
+            Token::Op(Operator::Assign),
+            Token::Number(1, "1".to_string()),
+            Token::Keyword(Keyword::Else),
+            Token::Identifier("x".to_string()),
+            Token::Op(Operator::Assign),
+            Token::Identifier("falsey".to_string()),
+            Token::End,
+        ];
+        assert_eq!(tokenize(src.to_string()).collect::<Vec<_>>(), expected);
+        assert_eq!(tokenize_native(src)?, expected);
+        assert_eq!(
+            tokenize("false".to_string()).next(),
+            Some(Token::Keyword(Keyword::False))
+        );
+        Ok(())
+    }
 }
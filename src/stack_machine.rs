@@ -1,63 +1,278 @@
 use anyhow::{Context, Result};
 
-#[derive(Debug, Clone)]
+use crate::{ast::IntVal, binop::apply_binop, error::EvalError};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum StackOperation {
     Push,
     Add,
     Sub,
     Mul,
     Div,
+    Pow,
+    Min,
+    Max,
+    // 先頭の値を複製する
+    Dup,
+    // 飛び先は命令列の添字。Dataも1つと数える。末尾(命令列の長さ)に飛ぶと終了する
+    Jump(usize),
+    // 先頭の値を取り出し、0なら飛ぶ
+    JumpIfZero(usize),
+    // 次の命令の位置を呼び出しスタックに積んで飛ぶ
+    Call(usize),
+    // 呼び出しスタックから取り出した位置に戻る
+    Ret,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum StackInstruction {
     Operation(StackOperation),
-    Data(i32),
+    Data(IntVal),
+}
+
+// 実行時の設定。wrappingならAdd/Sub/Mulのオーバーフローを2の補数で折り返し、
+// そうでなければエラーにする。resultは終了時のスタックから結果を取り出す方法
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExecOptions {
+    pub wrapping: bool,
+    pub result: FinalValue,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FinalValue {
+    // 先頭の値
+    #[default]
+    Top,
+    // 最初に積まれた値
+    Bottom,
+    // 全ての値の和。オーバーフローの扱いはAddと同じ
+    Sum,
+}
+
+pub fn execute(instructions: Vec<StackInstruction>, stack_values: Vec<IntVal>) -> Result<IntVal> {
+    execute_with(instructions, stack_values, ExecOptions::default())
 }
 
-pub fn execute(instructions: Vec<StackInstruction>, stack_values: Vec<i32>) -> Result<i32> {
-    let mut instructions = instructions.into_iter().rev().collect::<Vec<_>>();
+pub fn execute_with(
+    instructions: Vec<StackInstruction>,
+    stack_values: Vec<IntVal>,
+    options: ExecOptions,
+) -> Result<IntVal> {
     let mut stack = stack_values.to_vec();
-    while let Some(instruction) = instructions.pop() {
+    let mut pc = 0;
+    let mut call_stack = vec![];
+    while let Some(instruction) = instructions.get(pc) {
+        pc += 1;
         match instruction {
             StackInstruction::Operation(StackOperation::Push) => {
-                let operand = match instructions.pop().context("stack is empty")? {
-                    StackInstruction::Data(value) => value,
+                let operand = match instructions.get(pc).ok_or(EvalError::StackUnderflow)? {
+                    StackInstruction::Data(value) => *value,
                     _ => anyhow::bail!("expected a data value"),
                 };
+                pc += 1;
                 stack.push(operand);
             }
-            StackInstruction::Operation(StackOperation::Add) => {
-                let left = stack.pop().context("stack is empty")?;
-                let right = stack.pop().context("stack is empty")?;
-                stack.push(right + left);
+            StackInstruction::Operation(StackOperation::Dup) => {
+                let top = *stack.last().ok_or(EvalError::StackUnderflow)?;
+                stack.push(top);
+            }
+            StackInstruction::Operation(StackOperation::Jump(target)) => {
+                pc = jump_target(*target, instructions.len())?;
+            }
+            StackInstruction::Operation(StackOperation::JumpIfZero(target)) => {
+                let target = jump_target(*target, instructions.len())?;
+                if stack.pop().ok_or(EvalError::StackUnderflow)? == 0 {
+                    pc = target;
+                }
             }
-            StackInstruction::Operation(StackOperation::Sub) => {
-                let left = stack.pop().context("stack is empty")?;
-                let right = stack.pop().context("stack is empty")?;
-                stack.push(right - left);
+            StackInstruction::Operation(StackOperation::Call(target)) => {
+                call_stack.push(pc);
+                pc = jump_target(*target, instructions.len())?;
             }
-            StackInstruction::Operation(StackOperation::Mul) => {
-                let left = stack.pop().context("stack is empty")?;
-                let right = stack.pop().context("stack is empty")?;
-                stack.push(right * left);
+            StackInstruction::Operation(StackOperation::Ret) => {
+                pc = call_stack.pop().ok_or(EvalError::EmptyCallStack)?;
             }
-            StackInstruction::Operation(StackOperation::Div) => {
-                let left = stack.pop().context("stack is empty")?;
-                let right = stack.pop().context("stack is empty")?;
-                stack.push(right / left);
+            StackInstruction::Operation(operation) => {
+                let right = stack.pop().ok_or(EvalError::StackUnderflow)?;
+                let left = stack.pop().ok_or(EvalError::StackUnderflow)?;
+                stack.push(apply_operation(operation, left, right, options)?);
             }
             StackInstruction::Data(_) => {
                 anyhow::bail!("expected a operation value")
             }
         };
     }
-    stack.last().copied().context("stack is empty")
+    if stack.is_empty() {
+        anyhow::bail!(EvalError::StackUnderflow);
+    }
+    match options.result {
+        FinalValue::Top => Ok(stack[stack.len() - 1]),
+        FinalValue::Bottom => Ok(stack[0]),
+        FinalValue::Sum => stack[1..].iter().try_fold(stack[0], |sum, value| {
+            apply_operation(&StackOperation::Add, sum, *value, options)
+        }),
+    }
+}
+
+fn jump_target(target: usize, len: usize) -> Result<usize> {
+    if target > len {
+        anyhow::bail!(EvalError::JumpOutOfRange(target));
+    }
+    Ok(target)
+}
+
+// 2つの値を取る命令。leftが先に積まれた値
+fn apply_operation(
+    operation: &StackOperation,
+    left: IntVal,
+    right: IntVal,
+    options: ExecOptions,
+) -> Result<IntVal> {
+    let op = match operation {
+        StackOperation::Add if options.wrapping => return Ok(left.wrapping_add(right)),
+        StackOperation::Sub if options.wrapping => return Ok(left.wrapping_sub(right)),
+        StackOperation::Mul if options.wrapping => return Ok(left.wrapping_mul(right)),
+        StackOperation::Add => "+",
+        StackOperation::Sub => "-",
+        StackOperation::Mul => "*",
+        StackOperation::Div => "/",
+        StackOperation::Pow => "^",
+        StackOperation::Min => "min",
+        StackOperation::Max => "max",
+        StackOperation::Push
+        | StackOperation::Dup
+        | StackOperation::Jump(_)
+        | StackOperation::JumpIfZero(_)
+        | StackOperation::Call(_)
+        | StackOperation::Ret => {
+            anyhow::bail!("{:?} does not take operands", operation)
+        }
+    };
+    apply_binop(op, left, right)
+}
+
+// 1命令を1バイトのオペコードで表し、Dataの値と飛び先はその後ろに8バイトの
+// リトルエンディアンで続ける。オペコードの値は保存したプログラムとの互換のため変えない
+pub fn encode(instructions: &[StackInstruction]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for instruction in instructions {
+        match instruction {
+            StackInstruction::Data(value) => {
+                bytes.push(0x00);
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            StackInstruction::Operation(operation) => {
+                let (opcode, target) = match operation {
+                    StackOperation::Push => (0x01, None),
+                    StackOperation::Add => (0x02, None),
+                    StackOperation::Sub => (0x03, None),
+                    StackOperation::Mul => (0x04, None),
+                    StackOperation::Div => (0x05, None),
+                    StackOperation::Pow => (0x06, None),
+                    StackOperation::Min => (0x07, None),
+                    StackOperation::Max => (0x08, None),
+                    StackOperation::Dup => (0x09, None),
+                    StackOperation::Jump(target) => (0x0a, Some(*target)),
+                    StackOperation::JumpIfZero(target) => (0x0b, Some(*target)),
+                    StackOperation::Call(target) => (0x0c, Some(*target)),
+                    StackOperation::Ret => (0x0d, None),
+                };
+                bytes.push(opcode);
+                if let Some(target) = target {
+                    bytes.extend_from_slice(&(target as u64).to_le_bytes());
+                }
+            }
+        }
+    }
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<StackInstruction>> {
+    let mut instructions = Vec::new();
+    let mut rest = bytes;
+    while let Some((&opcode, tail)) = rest.split_first() {
+        rest = tail;
+        let instruction = match opcode {
+            0x00 => StackInstruction::Data(IntVal::from_le_bytes(read_operand(&mut rest)?)),
+            0x0a..=0x0c => {
+                let target = u64::from_le_bytes(read_operand(&mut rest)?);
+                let target = usize::try_from(target)
+                    .with_context(|| format!("jump target {} is too large", target))?;
+                StackInstruction::Operation(match opcode {
+                    0x0a => StackOperation::Jump(target),
+                    0x0b => StackOperation::JumpIfZero(target),
+                    _ => StackOperation::Call(target),
+                })
+            }
+            _ => StackInstruction::Operation(match opcode {
+                0x01 => StackOperation::Push,
+                0x02 => StackOperation::Add,
+                0x03 => StackOperation::Sub,
+                0x04 => StackOperation::Mul,
+                0x05 => StackOperation::Div,
+                0x06 => StackOperation::Pow,
+                0x07 => StackOperation::Min,
+                0x08 => StackOperation::Max,
+                0x09 => StackOperation::Dup,
+                0x0d => StackOperation::Ret,
+                _ => anyhow::bail!("unknown opcode {:#04x}", opcode),
+            }),
+        };
+        instructions.push(instruction);
+    }
+    Ok(instructions)
+}
+
+fn read_operand(rest: &mut &[u8]) -> Result<[u8; 8]> {
+    if rest.len() < 8 {
+        anyhow::bail!("operand is truncated");
+    }
+    let (operand, tail) = rest.split_at(8);
+    *rest = tail;
+    Ok(operand.try_into()?)
+}
+
+// 1行に1命令を番地付きで並べる。Pushは続くDataと合わせて1行にする
+pub fn disassemble(instructions: &[StackInstruction]) -> String {
+    let mut listing = String::new();
+    let mut pc = 0;
+    while let Some(instruction) = instructions.get(pc) {
+        let address = pc;
+        let line = match instruction {
+            StackInstruction::Operation(StackOperation::Push) => match instructions.get(pc + 1) {
+                Some(StackInstruction::Data(value)) => {
+                    pc += 1;
+                    format!("PUSH {}", value)
+                }
+                _ => "PUSH".to_string(),
+            },
+            StackInstruction::Operation(StackOperation::Jump(target)) => {
+                format!("JUMP {:04}", target)
+            }
+            StackInstruction::Operation(StackOperation::JumpIfZero(target)) => {
+                format!("JZ {:04}", target)
+            }
+            StackInstruction::Operation(StackOperation::Call(target)) => {
+                format!("CALL {:04}", target)
+            }
+            StackInstruction::Operation(operation) => format!("{:?}", operation).to_uppercase(),
+            StackInstruction::Data(value) => format!("DATA {}", value),
+        };
+        listing += &format!("{:04}  {}\n", address, line);
+        pc += 1;
+    }
+    listing
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::stack_machine::{execute, StackInstruction, StackOperation};
+    use crate::{
+        ast::IntVal,
+        stack_machine::{
+            decode, disassemble, encode, execute, execute_with, ExecOptions, FinalValue,
+            StackInstruction, StackOperation,
+        },
+    };
     use anyhow::Result;
 
     #[test]
@@ -93,4 +308,212 @@ mod tests {
         assert_eq!(res, 1);
         Ok(())
     }
+
+    #[test]
+    fn division_by_zero() {
+        let err = execute(
+            vec![
+                StackInstruction::Operation(StackOperation::Push),
+                StackInstruction::Data(0),
+                StackInstruction::Operation(StackOperation::Div),
+            ],
+            vec![1],
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "division by zero");
+    }
+
+    #[test]
+    fn pow() -> Result<()> {
+        let pow = |exp| {
+            execute(
+                vec![
+                    StackInstruction::Operation(StackOperation::Push),
+                    StackInstruction::Data(exp),
+                    StackInstruction::Operation(StackOperation::Pow),
+                ],
+                vec![2],
+            )
+        };
+        assert_eq!(pow(10)?, 1024);
+        assert!(pow(-1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn min_and_max() -> Result<()> {
+        let run = |operation| {
+            execute(
+                vec![
+                    StackInstruction::Operation(StackOperation::Push),
+                    StackInstruction::Data(7),
+                    StackInstruction::Operation(operation),
+                ],
+                vec![3],
+            )
+        };
+        assert_eq!(run(StackOperation::Min)?, 3);
+        assert_eq!(run(StackOperation::Max)?, 7);
+        Ok(())
+    }
+
+    #[test]
+    fn wrapping_and_checked() -> Result<()> {
+        let program = || {
+            vec![
+                StackInstruction::Operation(StackOperation::Push),
+                StackInstruction::Data(1),
+                StackInstruction::Operation(StackOperation::Add),
+            ]
+        };
+        let wrapping = ExecOptions {
+            wrapping: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            execute_with(program(), vec![IntVal::MAX], wrapping)?,
+            IntVal::MIN
+        );
+        let err = execute(program(), vec![IntVal::MAX]).unwrap_err();
+        assert!(err.to_string().starts_with("integer overflow"));
+        assert!(execute_with(program(), vec![IntVal::MAX], ExecOptions::default()).is_err());
+
+        // 折り返しでもゼロ除算はエラー
+        let div = vec![
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(0),
+            StackInstruction::Operation(StackOperation::Div),
+        ];
+        assert!(execute_with(div, vec![1], wrapping).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn countdown_loop() -> Result<()> {
+        // n := 5; while n do n := n - 1
+        let program = vec![
+            StackInstruction::Operation(StackOperation::Dup),
+            StackInstruction::Operation(StackOperation::JumpIfZero(6)),
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(1),
+            StackInstruction::Operation(StackOperation::Sub),
+            StackInstruction::Operation(StackOperation::Jump(0)),
+        ];
+        assert_eq!(execute(program.clone(), vec![5])?, 0);
+        assert_eq!(execute(program, vec![0])?, 0);
+
+        // 条件が0でなければ飛ばない
+        let program = vec![
+            StackInstruction::Operation(StackOperation::JumpIfZero(4)),
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(10),
+            StackInstruction::Operation(StackOperation::Jump(6)),
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(20),
+        ];
+        assert_eq!(execute(program.clone(), vec![1])?, 10);
+        assert_eq!(execute(program, vec![0])?, 20);
+        Ok(())
+    }
+
+    #[test]
+    fn jump_out_of_range() {
+        let program = vec![StackInstruction::Operation(StackOperation::Jump(3))];
+        let err = execute(program, vec![1]).unwrap_err();
+        assert_eq!(err.to_string(), "jump target 3 is out of range");
+    }
+
+    #[test]
+    fn call_and_ret() -> Result<()> {
+        // 先頭の値を2倍する5番地からのサブルーチンを2回呼ぶ
+        let program = vec![
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(21),
+            StackInstruction::Operation(StackOperation::Call(5)),
+            StackInstruction::Operation(StackOperation::Call(5)),
+            StackInstruction::Operation(StackOperation::Jump(8)),
+            StackInstruction::Operation(StackOperation::Dup),
+            StackInstruction::Operation(StackOperation::Add),
+            StackInstruction::Operation(StackOperation::Ret),
+        ];
+        assert_eq!(execute(program, vec![])?, 84);
+
+        let err = execute(
+            vec![StackInstruction::Operation(StackOperation::Ret)],
+            vec![1],
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "return with an empty call stack");
+        Ok(())
+    }
+
+    #[test]
+    fn encode_and_decode() -> Result<()> {
+        let program = vec![
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(-3),
+            StackInstruction::Operation(StackOperation::Call(6)),
+            StackInstruction::Operation(StackOperation::JumpIfZero(9)),
+            StackInstruction::Operation(StackOperation::Jump(9)),
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Operation(StackOperation::Dup),
+            StackInstruction::Operation(StackOperation::Mul),
+            StackInstruction::Operation(StackOperation::Ret),
+            StackInstruction::Data(IntVal::MAX),
+        ];
+        let bytes = encode(&program);
+        assert_eq!(&bytes[..2], &[0x01, 0x00]);
+        assert_eq!(decode(&bytes)?, program);
+
+        assert!(decode(&[0xff]).is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn disassemble_listing() {
+        let program = vec![
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(2),
+            StackInstruction::Operation(StackOperation::Dup),
+            StackInstruction::Operation(StackOperation::Add),
+            StackInstruction::Operation(StackOperation::JumpIfZero(6)),
+            StackInstruction::Operation(StackOperation::Ret),
+        ];
+        assert_eq!(
+            disassemble(&program),
+            "0000  PUSH 2\n0002  DUP\n0003  ADD\n0004  JZ 0006\n0005  RET\n"
+        );
+    }
+
+    #[test]
+    fn final_value() -> Result<()> {
+        let program = vec![
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(20),
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(3),
+        ];
+        let run = |result| {
+            let options = ExecOptions {
+                result,
+                ..Default::default()
+            };
+            execute_with(program.clone(), vec![1], options)
+        };
+        assert_eq!(run(FinalValue::Top)?, 3);
+        assert_eq!(run(FinalValue::Bottom)?, 1);
+        assert_eq!(run(FinalValue::Sum)?, 24);
+        assert_eq!(execute(program.clone(), vec![1])?, 3);
+        assert!(execute_with(
+            vec![],
+            vec![IntVal::MAX, 1],
+            ExecOptions {
+                result: FinalValue::Sum,
+                ..Default::default()
+            }
+        )
+        .is_err());
+        Ok(())
+    }
 }
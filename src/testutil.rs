@@ -0,0 +1,18 @@
+use crate::{
+    ast::{Expression, IntVal},
+    exec::Environment,
+};
+
+// expectedに挙げた変数だけを比べる。関数のreturnのような暗黙の束縛が増えてもテストが壊れない
+#[track_caller]
+pub fn assert_env_eq(actual: &Environment, expected: &[(&str, IntVal)]) {
+    for (name, value) in expected {
+        assert_eq!(
+            actual.get(*name),
+            Some(&Expression::Int { value: *value }),
+            "variable {} in {:?}",
+            name,
+            actual
+        );
+    }
+}
@@ -1,7 +1,85 @@
+use crate::ast::IntVal;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    While,
+    Do,
+    If,
+    Then,
+    Else,
+    Def,
+    True,
+    False,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Assign,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Lt,
+    Gt,
+    Eq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Punct {
+    Semicolon,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Comma,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Token {
-    KeyWord(String),
-    Number(i32),
+    Keyword(Keyword),
+    Op(Operator),
+    Punct(Punct),
+    // 値とソース上の綴り。007のような先頭の0もtextで復元できる
+    Number(IntVal, String),
     Identifier(String),
+    // preserve_triviaを有効にしたときだけ出てくる。Commentは先頭の"//"を含み、改行は含まない
+    Comment(String),
+    Whitespace(String),
     End,
 }
+
+impl Token {
+    // ソース上の綴り
+    pub fn text(&self) -> String {
+        let text = match self {
+            Token::Keyword(Keyword::While) => "while",
+            Token::Keyword(Keyword::Do) => "do",
+            Token::Keyword(Keyword::If) => "if",
+            Token::Keyword(Keyword::Then) => "then",
+            Token::Keyword(Keyword::Else) => "else",
+            Token::Keyword(Keyword::Def) => "def",
+            Token::Keyword(Keyword::True) => "true",
+            Token::Keyword(Keyword::False) => "false",
+            Token::Op(Operator::Assign) => ":=",
+            Token::Op(Operator::Plus) => "+",
+            Token::Op(Operator::Minus) => "-",
+            Token::Op(Operator::Star) => "*",
+            Token::Op(Operator::Slash) => "/",
+            Token::Op(Operator::Lt) => "<",
+            Token::Op(Operator::Gt) => ">",
+            Token::Op(Operator::Eq) => "=",
+            Token::Punct(Punct::Semicolon) => ";",
+            Token::Punct(Punct::LBrace) => "{",
+            Token::Punct(Punct::RBrace) => "}",
+            Token::Punct(Punct::LParen) => "(",
+            Token::Punct(Punct::RParen) => ")",
+            Token::Punct(Punct::Comma) => ",",
+            Token::Number(_, text)
+            | Token::Identifier(text)
+            | Token::Comment(text)
+            | Token::Whitespace(text) => text,
+            Token::End => "",
+        };
+        text.to_string()
+    }
+}
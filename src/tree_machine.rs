@@ -1,30 +1,23 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 
-use crate::ast::Expression;
+use crate::{
+    ast::{Expression, IntVal, Statement},
+    binop::apply_binop,
+    error::EvalError,
+    exec::restore_binding,
+};
+
+pub type Environment = HashMap<String, Expression>;
 
 pub fn apply_rule(expr: &Expression) -> Result<Expression> {
     match expr {
         Expression::BinExp { op, lhs, rhs } => match (&**lhs, &**rhs) {
             (Expression::Int { value: left_val }, Expression::Int { value: right_val }) => {
-                if op == "+" {
-                    Ok(Expression::Int {
-                        value: left_val + right_val,
-                    })
-                } else if op == "-" {
-                    Ok(Expression::Int {
-                        value: left_val - right_val,
-                    })
-                } else if op == "*" {
-                    Ok(Expression::Int {
-                        value: left_val * right_val,
-                    })
-                } else if op == "/" {
-                    Ok(Expression::Int {
-                        value: left_val / right_val,
-                    })
-                } else {
-                    anyhow::bail!("Unknown op: {}", op)
-                }
+                Ok(Expression::Int {
+                    value: apply_binop(op, *left_val, *right_val)?,
+                })
             }
             (Expression::BinExp { .. }, _) => {
                 let processed_lhs = apply_rule(lhs)?;
@@ -51,7 +44,101 @@ pub fn apply_rule(expr: &Expression) -> Result<Expression> {
 pub fn rewrite_loop(expr: Expression) -> Result<Expression> {
     match expr {
         Expression::Int { value } => Ok(Expression::Int { value }),
-        non_int @ _ => rewrite_loop(apply_rule(&non_int)?),
+        non_int => rewrite_loop(apply_rule(&non_int)?),
+    }
+}
+
+// 変数を環境の値で置き換え、書き換え規則だけで計算できる式にする
+fn substitute(expr: &Expression, env: &Environment) -> Result<Expression> {
+    match expr {
+        Expression::BinExp { op, lhs, rhs } => Ok(Expression::BinExp {
+            op: op.to_string(),
+            lhs: Box::new(substitute(lhs, env)?),
+            rhs: Box::new(substitute(rhs, env)?),
+        }),
+        Expression::Int { value } => Ok(Expression::Int { value: *value }),
+        Expression::Bool { value } => Ok(Expression::Int {
+            value: (*value).into(),
+        }),
+        Expression::Var { name } => env
+            .get(name)
+            .cloned()
+            .ok_or_else(|| EvalError::UnknownVariable(name.clone()).into()),
+        Expression::Call { .. } => anyhow::bail!("No applicable rule for: {:?}", expr),
+    }
+}
+
+fn evaluate(expr: &Expression, env: &Environment) -> Result<IntVal> {
+    match rewrite_loop(substitute(expr, env)?)? {
+        Expression::Int { value } => Ok(value),
+        other => anyhow::bail!(EvalError::TypeMismatch(other)),
+    }
+}
+
+// 文を実行する。式は書き換え規則の繰り返しで値にする
+pub fn execute(stmt: &Statement, env: &Environment) -> Result<Environment> {
+    match stmt {
+        Statement::If { cond, then, els } => {
+            if evaluate(cond, env)? != 0 {
+                execute(then, env)
+            } else {
+                execute(els, env)
+            }
+        }
+        Statement::Switch {
+            scrutinee,
+            cases,
+            default,
+        } => {
+            let value = evaluate(scrutinee, env)?;
+            let stmt = cases
+                .iter()
+                .find(|(case, _)| *case == value)
+                .map_or(default, |(_, stmt)| stmt);
+            execute(stmt, env)
+        }
+        Statement::While { cond, stmt } => {
+            let mut current_env = env.clone();
+            while evaluate(cond, &current_env)? != 0 {
+                current_env = execute(stmt, &current_env)?;
+            }
+            Ok(current_env)
+        }
+        Statement::DoWhile { stmt, cond } => {
+            let mut current_env = execute(stmt, env)?;
+            while evaluate(cond, &current_env)? != 0 {
+                current_env = execute(stmt, &current_env)?;
+            }
+            Ok(current_env)
+        }
+        Statement::Assign { name, expr } => {
+            let value = evaluate(expr, env)?;
+            let mut current_env = env.clone();
+            current_env.insert(name.to_string(), Expression::Int { value });
+            Ok(current_env)
+        }
+        Statement::Sequence { stmts } => {
+            let mut current_env = env.clone();
+            for stmt in stmts {
+                current_env = execute(stmt, &current_env)?;
+            }
+            Ok(current_env)
+        }
+        Statement::Assert { expr } => {
+            if evaluate(expr, env)? == 0 {
+                anyhow::bail!(EvalError::AssertionFailed((**expr).clone()));
+            }
+            Ok(env.clone())
+        }
+        Statement::Let { name, expr, body } => {
+            let value = evaluate(expr, env)?;
+            let mut current_env = env.clone();
+            current_env.insert(name.to_string(), Expression::Int { value });
+            let mut current_env = execute(body, &current_env)?;
+            restore_binding(&mut current_env, name.to_string(), env.get(name).cloned());
+            Ok(current_env)
+        }
+        Statement::FuncDef { .. } => anyhow::bail!("Unknown statement: {:?}", stmt),
     }
 }
 
@@ -59,9 +146,11 @@ pub fn rewrite_loop(expr: Expression) -> Result<Expression> {
 mod tests {
     use anyhow::Result;
 
-    use crate::ast::Expression;
+    use std::collections::HashMap;
+
+    use crate::{ast::Expression, parser::parse_program};
 
-    use super::rewrite_loop;
+    use super::{execute, rewrite_loop};
 
     #[test]
     fn four_arithmetic_ops1() -> Result<()> {
@@ -82,8 +171,25 @@ mod tests {
         if let Expression::Int { value } = res {
             assert_eq!(value, 1);
         } else {
-            assert!(false, "Expected Expression::Int, got {:?}", res);
+            panic!("Expected Expression::Int, got {:?}", res);
         }
         Ok(())
     }
+
+    #[test]
+    fn test_execute() -> Result<()> {
+        let stmt =
+            parse_program("i := 10; sum := 0; while 0 < i do { sum := sum + i; i := i - 1 }")?;
+        let env = execute(&stmt, &HashMap::new())?;
+        assert_eq!(env["sum"], Expression::Int { value: 55 });
+        assert!(execute(&parse_program("x := y")?, &HashMap::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_division_by_zero() -> Result<()> {
+        let err = execute(&parse_program("x := 0; y := 1 / x")?, &HashMap::new()).unwrap_err();
+        assert_eq!(err.to_string(), "division by zero");
+        Ok(())
+    }
 }
@@ -0,0 +1,135 @@
+use crate::ast::{Expression, Statement};
+
+// ASTを作り直しながらたどるパス。既定ではすべての子をたどって同じ木を返すので、
+// 変えたいノードのメソッドだけを上書きし、子を処理したいときはwalk_*を呼ぶ
+pub trait Fold {
+    fn fold_expr(&mut self, expr: Expression) -> Expression {
+        walk_expr(self, expr)
+    }
+
+    fn fold_stmt(&mut self, stmt: Statement) -> Statement {
+        walk_stmt(self, stmt)
+    }
+}
+
+// exprの子にfold_exprを適用する
+pub fn walk_expr<F: Fold + ?Sized>(folder: &mut F, expr: Expression) -> Expression {
+    match expr {
+        Expression::BinExp { op, lhs, rhs } => Expression::BinExp {
+            op,
+            lhs: Box::new(folder.fold_expr(*lhs)),
+            rhs: Box::new(folder.fold_expr(*rhs)),
+        },
+        Expression::Call { name, args } => Expression::Call {
+            name,
+            args: args
+                .into_iter()
+                .map(|arg| Box::new(folder.fold_expr(*arg)))
+                .collect(),
+        },
+        Expression::Int { .. } | Expression::Bool { .. } | Expression::Var { .. } => expr,
+    }
+}
+
+// stmtの子の文にfold_stmtを、式にfold_exprを適用する
+pub fn walk_stmt<F: Fold + ?Sized>(folder: &mut F, stmt: Statement) -> Statement {
+    match stmt {
+        Statement::If { cond, then, els } => Statement::If {
+            cond: Box::new(folder.fold_expr(*cond)),
+            then: Box::new(folder.fold_stmt(*then)),
+            els: Box::new(folder.fold_stmt(*els)),
+        },
+        Statement::Switch {
+            scrutinee,
+            cases,
+            default,
+        } => Statement::Switch {
+            scrutinee: Box::new(folder.fold_expr(*scrutinee)),
+            cases: cases
+                .into_iter()
+                .map(|(case, stmt)| (case, Box::new(folder.fold_stmt(*stmt))))
+                .collect(),
+            default: Box::new(folder.fold_stmt(*default)),
+        },
+        Statement::While { cond, stmt } => Statement::While {
+            cond: Box::new(folder.fold_expr(*cond)),
+            stmt: Box::new(folder.fold_stmt(*stmt)),
+        },
+        Statement::DoWhile { stmt, cond } => Statement::DoWhile {
+            stmt: Box::new(folder.fold_stmt(*stmt)),
+            cond: Box::new(folder.fold_expr(*cond)),
+        },
+        Statement::Assign { name, expr } => Statement::Assign {
+            name,
+            expr: Box::new(folder.fold_expr(*expr)),
+        },
+        Statement::Sequence { stmts } => Statement::Sequence {
+            stmts: stmts
+                .into_iter()
+                .map(|stmt| Box::new(folder.fold_stmt(*stmt)))
+                .collect(),
+        },
+        Statement::Assert { expr } => Statement::Assert {
+            expr: Box::new(folder.fold_expr(*expr)),
+        },
+        Statement::FuncDef { params, body } => Statement::FuncDef {
+            params,
+            body: Box::new(folder.fold_stmt(*body)),
+        },
+        Statement::Let { name, expr, body } => Statement::Let {
+            name,
+            expr: Box::new(folder.fold_expr(*expr)),
+            body: Box::new(folder.fold_stmt(*body)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{
+        ast::Expression,
+        parser::{parse_expression, parse_program},
+    };
+
+    use super::{walk_expr, Fold};
+
+    // 整数リテラルをすべて1増やす
+    struct Increment;
+
+    impl Fold for Increment {
+        fn fold_expr(&mut self, expr: Expression) -> Expression {
+            match expr {
+                Expression::Int { value } => Expression::Int { value: value + 1 },
+                _ => walk_expr(self, expr),
+            }
+        }
+    }
+
+    // 何も上書きしなければ同じ木に戻る
+    struct Identity;
+
+    impl Fold for Identity {}
+
+    #[test]
+    fn test_fold() -> Result<()> {
+        let expr = parse_expression("1 + f(x, 2) * 3")?;
+        assert_eq!(
+            Increment.fold_expr(expr),
+            parse_expression("2 + f(x, 3) * 4")?
+        );
+
+        let stmt = parse_program(
+            "i := 0; while i < 10 do { if i > 5 then x := i else x := 1; i := i + 1 }",
+        )?;
+        assert_eq!(
+            Increment.fold_stmt(stmt.clone()),
+            parse_program(
+                "i := 1; while i < 11 do { if i > 6 then x := i else x := 2; i := i + 2 }"
+            )?
+        );
+        assert_eq!(Identity.fold_stmt(stmt.clone()), stmt);
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::{fs, path::PathBuf, process::Command};
+
+use anyhow::Result;
+use fake_world_wonderland::{
+    ast::Expression,
+    cli::{run_file, Backend},
+};
+
+const SUM_TO_10: &str = "
+{
+    i := 10;
+    sum := 0;
+    while 0 < i do {
+        sum := sum + i;
+        i := i - 1
+    }
+}
+";
+
+fn write_source(name: &str, src: &str) -> Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+    fs::write(&path, src)?;
+    Ok(path)
+}
+
+#[test]
+fn test_run_file() -> Result<()> {
+    let path = write_source("sum.fww", SUM_TO_10)?;
+    let env = run_file(&path, Backend::Cbv)?;
+    let tree_env = run_file(&path, Backend::Tree)?;
+    fs::remove_file(&path)?;
+    assert_eq!(env["sum"], Expression::Int { value: 55 });
+    assert_eq!(env["i"], Expression::Int { value: 0 });
+    assert_eq!(tree_env, env);
+    Ok(())
+}
+
+#[test]
+fn test_binary_runs_file() -> Result<()> {
+    let path = write_source("bin-sum.fww", SUM_TO_10)?;
+    for backend in [None, Some("cbn"), Some("tree")] {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_fake-world-wonderland"));
+        if let Some(backend) = backend {
+            command.args(["--backend", backend]);
+        }
+        let output = command.arg(&path).output()?;
+        assert!(output.status.success(), "{:?}", backend);
+        assert_eq!(String::from_utf8(output.stdout)?, "i = 0\nsum = 55\n");
+    }
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[test]
+fn test_binary_reports_errors() -> Result<()> {
+    let path = write_source("broken.fww", "i := (1 + 2")?;
+    let output = Command::new(env!("CARGO_BIN_EXE_fake-world-wonderland"))
+        .arg(&path)
+        .output()?;
+    fs::remove_file(&path)?;
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)?.starts_with("error: failed to run"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_fake-world-wonderland"))
+        .arg("/nonexistent/program.fww")
+        .output()?;
+    assert!(!output.status.success());
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use fake_world_wonderland::{
+    ast::Expression,
+    exec::eval_source,
+    fsa::NFA,
+    regexp::RegExp,
+    rx_to_fsa::NFAConstructor,
+    stack_machine::{execute, StackInstruction, StackOperation},
+};
+
+#[test]
+fn test_eval_source() -> Result<()> {
+    let env = eval_source("i := 3; sum := 0; while i do { sum := sum + i; i := i - 1 }")?;
+    assert_eq!(env["sum"], Expression::Int { value: 6 });
+    Ok(())
+}
+
+#[test]
+fn test_stack_machine() -> Result<()> {
+    let res = execute(
+        vec![
+            StackInstruction::Operation(StackOperation::Push),
+            StackInstruction::Data(2),
+            StackInstruction::Operation(StackOperation::Mul),
+        ],
+        vec![21],
+    )?;
+    assert_eq!(res, 42);
+    Ok(())
+}
+
+#[test]
+fn test_regexp_to_dfa() {
+    let rx = RegExp::Seq {
+        left: Box::new(RegExp::Char('a')),
+        right: Box::new(RegExp::Repeat(Box::new(RegExp::Char('b')))),
+    };
+    let nfa: NFA = NFAConstructor::new()
+        .rx_to_nfa(&rx, &HashSet::from(['a', 'b']))
+        .expect("Failed to convert RegExp to NFA");
+    let dfa = nfa.to_dfa();
+    assert!(dfa.try_accept("abbb"));
+    assert!(!dfa.try_accept("ba"));
+    assert_eq!(rx._match("ab", 0), Some(HashSet::from([1, 2])));
+}